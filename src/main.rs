@@ -4,7 +4,10 @@
 use nalgebra as na;
 
 mod map;
-use map::{Map, SEA_LEVEL};
+mod voronoi;
+use imageproc::drawing::draw_filled_circle_mut;
+use map::{FallKind, Falls, Map, Mouths, Shore, Vegetation, HEIGHT_SCALE, SEA_LEVEL};
+use voronoi::Layers;
 
 #[allow(unused_variables)]
 fn draw_map(map: &Map, label: &str) {
@@ -48,13 +51,13 @@ fn draw_map(map: &Map, label: &str) {
             // The dot product of 2 unit vectors is the same as the cos of the angle between them
             // http://learnwebgl.brown37.net/09_lights/lights_diffuse.html
             let light = normal.dot(&sun).clamp(0.0, 1.0);
+            // Snow and ice blend our land towards white
+            let snow = map.get_snow(x, y);
+            let [r, g, b] = [[108.0, 245.0], [152.0, 248.0], [95.0, 252.0]]
+                .map(|[land, snow_color]| land + (snow_color - land) * snow);
             // Each of the RGB components is multiplied by the dot product (acting as a percentage
             // of the light hitting the surface)
-            image::Rgb([
-                (108.0 * light) as u8,
-                (152.0 * light) as u8,
-                (95.0 * light) as u8,
-            ])
+            image::Rgb([(r * light) as u8, (g * light) as u8, (b * light) as u8])
 
             // image::Rgb([
             //     (255.0 * height) as u8,
@@ -66,27 +69,205 @@ fn draw_map(map: &Map, label: &str) {
         *pixel = color;
     }
 
-    // // Draw rivers
-    // let river = image::Rgb([70_u8, 107, 159]);
-    // for ((x1, y1), (x2, y2)) in map.get_river_segments() {
-    //     draw_line_segment_mut(
-    //         &mut img,
-    //         (x1 as f32, y1 as f32),
-    //         (x2 as f32, y2 as f32),
-    //         river,
-    //     );
-    // }
-
-    // let sand = image::Rgb([160_u8, 144, 119]);
-    // for (x, y) in map.get_coast() {
-    //     img.put_pixel(*x, *y, sand);
-    // }
+    draw_rivers(&mut img, map);
+    draw_falls(&mut img, map);
+
+    let sand = image::Rgb([160_u8, 144, 119]);
+    let rock = image::Rgb([96_u8, 90, 84]);
+    for shore in map.get_shores() {
+        match shore.shore {
+            Shore::Beach => img.put_pixel(shore.x, shore.y, sand),
+            Shore::Cliff => img.put_pixel(shore.x, shore.y, rock),
+            Shore::Rocky => (),
+        }
+    }
 
     img.save(format!("noise_map_{}.png", label)).unwrap();
 }
 
+/// Draw each of our drainage basins in its own colour, with their rivers over them
+fn draw_basins(map: &Map, label: &str) {
+    let mut img = image::ImageBuffer::new(map.size(), map.size());
+    let sun = na::Vector3::new(-0.25, 0.75, -1.5).normalize();
+    let watersheds = map.get_watersheds();
+
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let basin = match map.get_basin(x, y) {
+            Some(basin) => basin,
+            None => {
+                *pixel = image::Rgb([70_u8, 107, 159]);
+                continue;
+            }
+        };
+
+        // Basins with rivers get their own hue, spread around the colour wheel by the golden ratio
+        // so that neighbors rarely look alike; the rest of the land is left a plain grey
+        let color = if watersheds[basin].river().is_some() {
+            hue((basin as f64 * 0.618_033_988_75).fract())
+        } else {
+            [150.0, 150.0, 140.0]
+        };
+
+        let light = map.get_normal(x, y).dot(&sun).clamp(0.0, 1.0);
+        *pixel = image::Rgb(color.map(|c| (c * light) as u8));
+    }
+
+    draw_rivers(&mut img, map);
+
+    img.save(format!("basin_map_{}.png", label)).unwrap();
+}
+
+/// A fully saturated colour of the given hue, in the range [0.0, 1.0)
+fn hue(hue: f64) -> [f64; 3] {
+    let h = hue * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let [r, g, b] = match h as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    };
+
+    // Soften our colours a little so the terrain's shading still shows through
+    [r, g, b].map(|c| 90.0 + 150.0 * c)
+}
+
+/// Draw our rivers as smooth, antialiased lines, wider and deeper in colour downstream
+fn draw_rivers(img: &mut image::RgbImage, map: &Map) {
+    let rivers = map.get_rivers();
+    let points = rivers.iter().flatten();
+    let min_flow = points.clone().map(|p| p.flow).fold(f64::MAX, f64::min);
+    let max_order = points.map(|p| u32::from(p.order)).max().unwrap_or(0);
+
+    // How strongly each pixel is covered by a river, and how deep a river; we keep the strongest
+    // so that overlapping strokes don't build up darker spots
+    let size = map.size();
+    let mut coverage = vec![(0.0_f64, 0.0_f64); (size * size) as usize];
+
+    for river in rivers.iter() {
+        // Rivers widen as their flow grows, and deepen in colour with their Strahler order
+        let path = river
+            .iter()
+            .map(|p| {
+                [
+                    f64::from(p.x),
+                    f64::from(p.y),
+                    0.5 + 0.25 * (p.flow / min_flow).log2().max(0.0),
+                    f64::from(u32::from(p.order)) / f64::from(max_order.max(1)),
+                ]
+            })
+            .collect();
+
+        for pair in chaikin(path, 3).windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let length = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+            let steps = (length * 2.0).ceil().max(1.0) as u32;
+
+            // Stamp antialiased discs along the segment, half a pixel apart
+            for step in 0..=steps {
+                let t = f64::from(step) / f64::from(steps);
+                let [x, y, radius, depth] = [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t);
+
+                let reach = radius + 1.0;
+                let (x0, x1) = ((x - reach).floor().max(0.0), (x + reach).ceil());
+                let (y0, y1) = ((y - reach).floor().max(0.0), (y + reach).ceil());
+                for py in (y0 as u32)..=(y1 as u32).min(size - 1) {
+                    for px in (x0 as u32)..=(x1 as u32).min(size - 1) {
+                        let d = ((f64::from(px) - x).powi(2) + (f64::from(py) - y).powi(2)).sqrt();
+                        let cover = (radius + 0.5 - d).clamp(0.0, 1.0);
+
+                        let pixel = &mut coverage[(px + py * size) as usize];
+                        pixel.0 = pixel.0.max(cover);
+                        if cover > 0.0 {
+                            pixel.1 = pixel.1.max(depth);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let shallow = [110.0, 150.0, 190.0];
+    let deep = [45.0, 80.0, 135.0];
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let (cover, depth) = coverage[(x + y * size) as usize];
+        if cover <= 0.0 {
+            continue;
+        }
+
+        for c in 0..3 {
+            let river = shallow[c] + (deep[c] - shallow[c]) * depth;
+            pixel[c] = (f64::from(pixel[c]) + (river - f64::from(pixel[c])) * cover) as u8;
+        }
+    }
+}
+
+/// Mark our waterfalls with white-water discs sized by their height, and rapids with flecks of
+/// foam
+fn draw_falls(img: &mut image::RgbImage, map: &Map) {
+    let foam = image::Rgb([225_u8, 235, 240]);
+    let spray = image::Rgb([255_u8, 255, 255]);
+
+    for fall in map.find_falls(&Falls::default()) {
+        let (x, y) = (fall.x as i32, fall.y as i32);
+        match fall.kind {
+            FallKind::Waterfall => {
+                let radius = (1.5 + fall.height * HEIGHT_SCALE).min(4.0);
+                draw_filled_circle_mut(img, (x, y), radius as i32 + 1, image::Rgb([40, 50, 60]));
+                draw_filled_circle_mut(img, (x, y), radius as i32, spray);
+            }
+            FallKind::Rapids => {
+                // Fleck every other cell with foam, down the river to the foot of the rapids
+                let river = map.get_watersheds()[fall.watershed].river().unwrap();
+                let (mut last, mut travelled) = ((fall.x, fall.y), 0.0);
+                for (i, id) in river.downstream(fall.node).enumerate() {
+                    let (px, py) = map.from_idx(river.node(id).idx);
+                    travelled +=
+                        f64::from(px.abs_diff(last.0)).hypot(f64::from(py.abs_diff(last.1)));
+                    if travelled > fall.length {
+                        break;
+                    }
+                    if i % 2 == 0 {
+                        img.put_pixel(px, py, foam);
+                    }
+                    last = (px, py);
+                }
+            }
+        }
+    }
+}
+
+/// Smooth a path by Chaikin's corner cutting, keeping its ends in place
+///
+/// Each point carries extra values (such as width) alongside its position, which are smoothed
+/// along with it.
+fn chaikin(mut path: Vec<[f64; 4]>, iterations: u32) -> Vec<[f64; 4]> {
+    for _ in 0..iterations {
+        if path.len() < 3 {
+            break;
+        }
+
+        let mut smoothed = Vec::with_capacity(path.len() * 2);
+        smoothed.push(path[0]);
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            smoothed.push([0, 1, 2, 3].map(|i| a[i] * 0.75 + b[i] * 0.25));
+            smoothed.push([0, 1, 2, 3].map(|i| a[i] * 0.25 + b[i] * 0.75));
+        }
+        smoothed.push(path[path.len() - 1]);
+
+        path = smoothed;
+    }
+
+    path
+}
+
 fn main() {
     let size = 800;
+    // Foliage is only exported for our engine when asked for, rather than on every run
+    let export_foliage = std::env::args().any(|arg| arg == "--foliage");
 
     for seed in 0..12 {
         println!("Generating island {}...", seed + 1);
@@ -104,6 +285,20 @@ fn main() {
         draw_map(&map, &format!("{:02}d", seed + 1));
 
         map.erode(50_000);
+        map.form_mouths(&Mouths::default());
         draw_map(&map, &format!("{:02}e", seed + 1));
+        draw_basins(&map, &format!("{:02}", seed + 1));
+        if export_foliage {
+            map.grow_foliage(&Vegetation::default())
+                .save_csv(format!("foliage_{:02}.csv", seed + 1))
+                .unwrap();
+        }
+
+        map.polygons()
+            .save_svg(
+                format!("polygon_map_{:02}.svg", seed + 1),
+                &Layers::default(),
+            )
+            .unwrap();
     }
 }
@@ -1,41 +1,402 @@
 use nalgebra as na;
 use rand::prelude::*;
 use rand_xoshiro::Xoshiro256StarStar;
+use std::cell::OnceCell;
 
+use crate::voronoi::{Sampling, Voronoi};
+
+mod bathymetry;
+mod coast;
+mod drainage;
 mod elevation;
 mod erosion;
+mod falls;
 mod gradient;
-//mod watershed;
+mod grid;
+mod math;
+mod mouths;
+mod noise;
+mod shallow_water;
+mod shape;
+mod snow;
+mod spine;
+mod strahler;
+mod stream_power;
+mod thermal;
+mod valleys;
+mod vegetation;
+mod watershed;
+pub use bathymetry::Bathymetry;
+pub use coast::{CoastCell, Coastal, Shore};
+pub use drainage::Queued;
 use elevation::Elevation;
+pub use elevation::HEIGHT_SCALE;
+pub use falls::{Fall, FallKind, Falls};
+pub use math::rank_moisture;
+#[allow(unused_imports)]
+pub use mouths::{MouthKind, Mouths, RiverMouth};
+#[allow(unused_imports)]
+pub use noise::{EmptyCombinator, Noise, NoiseError};
+pub use shape::{IslandShape, Mask, ShapeKind};
+pub use snow::{Glaciers, Snow};
+pub use spine::Spine;
+pub use strahler::Strahler;
+pub use stream_power::StreamPower;
+pub use thermal::Thermal;
+pub use valleys::Valleys;
+#[allow(unused_imports)]
+pub use vegetation::{Foliage, Plant, Species, Vegetation};
+#[allow(unused_imports)]
+pub use watershed::river::{Node, NodeId, River};
+pub use watershed::{RiverPoint, Watershed};
 
 pub const SEA_LEVEL: f64 = 0.0;
 
+/// Everything that decides how a new map's terrain is generated
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// The overall shape of our island
+    pub shape: ShapeKind,
+    /// An optional mask to draw the island's outline
+    pub mask: Option<Mask>,
+    /// How strongly `mask` replaces `shape`, from 0.0 to 1.0
+    pub mask_weight: f64,
+    /// The noise that gives our terrain its detail
+    pub noise: Noise,
+    /// Mountain ranges to raise along the island
+    pub spines: Vec<Spine>,
+    /// How many more mountain ranges to generate at random
+    pub random_spines: u32,
+}
+
+/// Our drainage, and the rivers and basins traced over it
+struct Rivers {
+    drainage: drainage::Drainage,
+    watersheds: Vec<Watershed>,
+    /// The watershed each cell drains into, or `None` in the ocean
+    basins: grid::Grid<Option<usize>>,
+}
+
+impl Rivers {
+    fn trace(elevation: &Elevation) -> Self {
+        let drainage = drainage::Drainage::new(elevation);
+        let (watersheds, basins) = Watershed::create_all(&drainage, elevation);
+
+        Self {
+            drainage,
+            watersheds,
+            basins,
+        }
+    }
+}
+
 pub struct Map {
     size: u32,
     rng: Xoshiro256StarStar,
     elevation: Elevation,
-    //watersheds: Vec<watershed::Watershed>,
+    shape: Box<dyn IslandShape>,
+    brush: erosion::Brush,
+    thermal: Option<Thermal>,
+    shores: Vec<CoastCell>,
+    water: Option<shallow_water::ShallowWater>,
+    /// Our rivers, traced the first time they're needed after the terrain changes
+    rivers: OnceCell<Rivers>,
+    /// Sediment our rivers have carried into the sea, at the cell where it arrived
+    sediment: grid::Grid<f64>,
+    mouths: Vec<RiverMouth>,
+    snow: Snow,
+    /// How thickly glaciers cover each cell, from 0.0 to 1.0
+    ice: grid::Grid<f64>,
 }
 
 impl Map {
     pub fn new(seed: u64, size: u32) -> Self {
+        Self::with_shape(seed, size, ShapeKind::default())
+    }
+
+    /// Generate a new map, using the given kind of shape for the island
+    pub fn with_shape(seed: u64, size: u32, shape: ShapeKind) -> Self {
+        Self::with_settings(
+            seed,
+            size,
+            Settings {
+                shape,
+                ..Default::default()
+            },
+        )
+        .expect("default noise tree is valid")
+    }
+
+    /// Generate a new map with its outline drawn by `mask`
+    ///
+    /// A `weight` of 1.0 replaces our usual gradient with the mask entirely, while lower weights
+    /// blend the two together. Either way the map keeps its border of open ocean.
+    #[allow(dead_code)]
+    pub fn with_mask(seed: u64, size: u32, mask: Mask, weight: f64) -> Self {
+        Self::with_settings(
+            seed,
+            size,
+            Settings {
+                mask: Some(mask),
+                mask_weight: weight,
+                ..Default::default()
+            },
+        )
+        .expect("default noise tree is valid")
+    }
+
+    /// Generate a new map from the given settings
+    ///
+    /// Fails if `settings.noise` has a combinator with no inputs.
+    pub fn with_settings(
+        seed: u64,
+        size: u32,
+        settings: Settings,
+    ) -> Result<Self, EmptyCombinator> {
         let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
-        let elevation = Elevation::new(&mut rng, size);
+        // Our shape defines the overall outline of our island
+        let mut shape = settings.shape.generate(&mut rng);
+        if let Some(mask) = settings.mask {
+            shape = Box::new(mask.blend(shape, settings.mask_weight));
+        }
 
-        let map = Map {
+        let mut spines = settings.spines;
+        for _ in 0..settings.random_spines {
+            spines.push(Spine::random(&mut rng));
+        }
+
+        let elevation = Elevation::new(&mut rng, size, shape.as_ref(), &settings.noise, &spines)?;
+
+        Ok(Map {
             size,
             rng,
             elevation,
-            //watersheds: Vec::new(),
-        };
+            shape,
+            brush: Default::default(),
+            thermal: None,
+            shores: Vec::new(),
+            water: None,
+            rivers: OnceCell::new(),
+            sediment: grid::Grid::new(size, 0.0),
+            mouths: Vec::new(),
+            snow: Default::default(),
+            ice: grid::Grid::new(size, 0.0),
+        })
+    }
 
-        //map.watersheds = watershed::Watershed::create_all(&map);
+    pub fn erode(&mut self, cycles: u32) {
+        match &self.thermal {
+            None => erosion::erode(
+                &mut self.elevation,
+                &mut self.rng,
+                cycles,
+                &self.brush,
+                &mut self.sediment,
+            ),
+            Some(thermal) => {
+                // Interleave our droplets with thermal passes so slopes relax as they're carved
+                let mut remaining = cycles;
+                while remaining > 0 {
+                    let batch = remaining.min(thermal.interval.max(1));
+                    erosion::erode(
+                        &mut self.elevation,
+                        &mut self.rng,
+                        batch,
+                        &self.brush,
+                        &mut self.sediment,
+                    );
+                    thermal::erode(&mut self.elevation, thermal, 1);
 
-        map
+                    remaining -= batch;
+                }
+            }
+        }
+
+        self.invalidate_rivers();
     }
 
-    pub fn erode(&mut self, cycles: u32) {
-        erosion::erode(&mut self.elevation, &mut self.rng, cycles);
+    /// Run thermal erosion on its own, relaxing slopes steeper than the talus angle
+    #[allow(dead_code)]
+    pub fn erode_thermal(&mut self, iterations: u32) {
+        let thermal = self.thermal.clone().unwrap_or_default();
+        thermal::erode(&mut self.elevation, &thermal, iterations);
+        self.invalidate_rivers();
+    }
+
+    /// Interleave thermal erosion with hydraulic erosion in `erode`, or stop doing so with `None`
+    #[allow(dead_code)]
+    pub fn set_thermal_erosion(&mut self, thermal: Option<Thermal>) {
+        self.thermal = thermal;
+    }
+
+    /// Set the radius, in cells, over which each droplet erodes the terrain
+    #[allow(dead_code)]
+    pub fn set_erosion_radius(&mut self, radius: u32) {
+        self.brush = erosion::Brush::new(radius);
+    }
+
+    /// Evolve the landscape under tectonic uplift and river incision
+    ///
+    /// Uplift is strongest where our shape is highest, raising the island's core.
+    #[allow(dead_code)]
+    pub fn evolve(&mut self, stream_power: &StreamPower, steps: u32) {
+        let uplift = StreamPower::uplift_from(self.shape.as_ref(), self.size);
+        stream_power.evolve(&mut self.elevation, &uplift, steps);
+        self.invalidate_rivers();
+    }
+
+    /// Wear back exposed coast into cliffs and build beaches in sheltered bays
+    #[allow(dead_code)]
+    pub fn shape_coast(&mut self, coastal: &Coastal) {
+        self.shores = coastal.shape(&mut self.elevation);
+        self.invalidate_rivers();
+    }
+
+    /// Carve our rivers into the terrain, cutting channels and valleys sized by their order
+    #[allow(dead_code)]
+    pub fn carve_valleys(&mut self, valleys: &Valleys) {
+        let rivers = self.get_rivers();
+        let traced = self.take_rivers();
+        valleys.carve(&mut self.elevation, &traced.drainage, &rivers);
+    }
+
+    /// Build deltas and drown estuaries at our river mouths, as `mouths` describes
+    ///
+    /// Deltas are built from the sediment our rivers have carried to the sea in `erode`, which is
+    /// used up in the process.
+    pub fn form_mouths(&mut self, mouths: &Mouths) {
+        let rivers = self.take_rivers();
+        self.mouths = mouths.form(
+            &mut self.elevation,
+            &rivers.watersheds,
+            &rivers.drainage,
+            &mut self.sediment,
+            &mut self.rng,
+        );
+        self.elevation.update_coast();
+    }
+
+    /// Our river mouths, as found by `form_mouths`
+    #[allow(dead_code)]
+    pub fn get_mouths(&self) -> &[RiverMouth] {
+        &self.mouths
+    }
+
+    /// Let glaciers build up on our peaks and flow down the valleys, carving them into troughs
+    #[allow(dead_code)]
+    pub fn glaciate(&mut self, glaciers: &Glaciers) {
+        self.ice = glaciers.run(&mut self.elevation);
+        self.elevation.update_coast();
+        self.invalidate_rivers();
+    }
+
+    /// Change where snow lies on our peaks
+    #[allow(dead_code)]
+    pub fn set_snow(&mut self, snow: Snow) {
+        self.snow = snow;
+    }
+
+    /// How thickly (x, y) is covered in snow and ice, from 0.0 for bare ground to 1.0
+    pub fn get_snow(&self, x: u32, y: u32) -> f64 {
+        self.snow.at(&self.elevation, &self.ice, x, y)
+    }
+
+    /// Shape the ocean floor into a continental shelf, slope, and abyssal plain
+    #[allow(dead_code)]
+    pub fn shape_seafloor(&mut self, bathymetry: &Bathymetry) {
+        bathymetry.shape(&mut self.elevation, self.rng.gen());
+        self.invalidate_rivers();
+    }
+
+    /// The kind of shore at each coast cell, as found by `shape_coast`
+    pub fn get_shores(&self) -> &[CoastCell] {
+        &self.shores
+    }
+
+    /// Run the shallow water simulation for the given number of time steps
+    ///
+    /// Water persists between calls, so repeated calls continue the same simulation.
+    #[allow(dead_code)]
+    pub fn simulate_water(&mut self, steps: u32) {
+        let size = self.size;
+        self.water
+            .get_or_insert_with(|| shallow_water::ShallowWater::new(size, Default::default()))
+            .run(&mut self.elevation, steps);
+        self.invalidate_rivers();
+    }
+
+    /// Forget our rivers after the terrain has changed, to be traced again when next needed
+    fn invalidate_rivers(&mut self) {
+        self.rivers = OnceCell::new();
+    }
+
+    /// Our rivers, tracing them first if the terrain has changed since we last did
+    fn rivers(&self) -> &Rivers {
+        self.rivers.get_or_init(|| Rivers::trace(&self.elevation))
+    }
+
+    /// Take our rivers, tracing them first if need be, for a change to the terrain that needs them
+    ///
+    /// This leaves them to be traced again afterwards.
+    fn take_rivers(&mut self) -> Rivers {
+        let elevation = &self.elevation;
+        self.rivers
+            .take()
+            .unwrap_or_else(|| Rivers::trace(elevation))
+    }
+
+    /// The courses of all our rivers and their tributaries
+    ///
+    /// Each course runs upstream, from where it meets the sea or the river it flows into.
+    pub fn get_rivers(&self) -> Vec<Vec<RiverPoint>> {
+        let rivers = self.rivers();
+        rivers
+            .watersheds
+            .iter()
+            .flat_map(|watershed| watershed.river_paths(self, &rivers.drainage))
+            .collect()
+    }
+
+    /// Our drainage basins, one for each outlet to the sea
+    pub fn get_watersheds(&self) -> &[Watershed] {
+        &self.rivers().watersheds
+    }
+
+    /// The index within `get_watersheds` of the watershed that (x, y) drains into, if it's on land
+    pub fn get_basin(&self, x: u32, y: u32) -> Option<usize> {
+        self.rivers().basins[(x, y)]
+    }
+
+    /// Find the waterfalls and rapids along our rivers, as `falls` describes
+    pub fn find_falls(&self, falls: &Falls) -> Vec<Fall> {
+        falls.find(&self.elevation, &self.rivers().watersheds)
+    }
+
+    /// Grow vegetation over our land, as `vegetation` describes
+    pub fn grow_foliage(&mut self, vegetation: &Vegetation) -> Foliage {
+        let seed = self.rng.gen();
+        vegetation.grow(self, seed)
+    }
+
+    /// Build a polygon map from this map's terrain, with biomes and rivers
+    #[allow(dead_code)]
+    pub fn polygons(&mut self) -> Voronoi {
+        self.polygons_with(&Sampling::default())
+    }
+
+    /// Build a polygon map from this map's terrain, with its cells placed as `sampling` describes
+    #[allow(dead_code)]
+    pub fn polygons_with(&mut self, sampling: &Sampling) -> Voronoi {
+        let seed = self.rng.gen();
+        Voronoi::from_map(self, seed, sampling)
+    }
+
+    /// Depth of surface water at (x, y), as found by `simulate_water`
+    #[allow(dead_code)]
+    pub fn get_water_depth(&self, x: u32, y: u32) -> f64 {
+        self.water
+            .as_ref()
+            .map_or(0.0, |water| water.water()[(x, y)])
     }
 
     #[allow(dead_code)]
@@ -44,9 +405,10 @@ impl Map {
         self.elevation.to_idx(x, y)
     }
 
-    #[allow(dead_code)]
+    /// The (x, y) coordinates of the cell at index `idx`
+    #[allow(clippy::wrong_self_convention)]
     #[inline(always)]
-    fn from_idx(&self, idx: usize) -> (u32, u32) {
+    pub fn from_idx(&self, idx: usize) -> (u32, u32) {
         self.elevation.from_idx(idx)
     }
 
@@ -56,8 +418,8 @@ impl Map {
     }
 
     #[allow(dead_code)]
-    pub fn get_coast<'a>(&'a self) -> &'a Vec<(u32, u32)> {
-        &self.elevation.get_coast()
+    pub fn get_coast(&self) -> &Vec<(u32, u32)> {
+        self.elevation.get_coast()
     }
 
     #[inline(always)]
@@ -65,6 +427,24 @@ impl Map {
         self.elevation[(x, y)]
     }
 
+    /// Sample the elevation at any point between cells
+    #[allow(dead_code)]
+    pub fn sample_elevation(&self, x: f64, y: f64) -> f64 {
+        self.elevation.sample(x, y)
+    }
+
+    /// Whether (x, y) is part of the open ocean, as opposed to dry land or an inland lake
+    #[allow(dead_code)]
+    pub fn is_ocean(&self, x: u32, y: u32) -> bool {
+        self.elevation.ocean()[(x, y)]
+    }
+
+    /// Depth of the water at (x, y), or 0 on dry land
+    #[allow(dead_code)]
+    pub fn get_depth(&self, x: u32, y: u32) -> f64 {
+        (SEA_LEVEL - self.elevation[(x, y)]).max(0.0)
+    }
+
     #[allow(dead_code)]
     pub fn get_normal(&self, x: u32, y: u32) -> na::Vector3<f64> {
         self.elevation.get_normal(x, y)
@@ -0,0 +1,240 @@
+//! Shape the ocean floor
+//!
+//! Depth is driven by distance from the coast: a gently sloping continental shelf gives way at
+//! the shelf break to a steeper continental slope, which levels out onto the abyssal plain. Noise
+//! roughens the floor, raises shoals on the shelf, and builds reefs just beneath the surface.
+
+use super::elevation::Elevation;
+use super::grid::Grid;
+use super::math::ramp;
+use super::SEA_LEVEL;
+use bracket_noise::prelude::*;
+use std::f64::consts::SQRT_2;
+
+/// Parameters for shaping the ocean floor
+///
+/// Widths are in cells, and depths are below sea level (so are positive).
+#[derive(Debug, Clone)]
+pub struct Bathymetry {
+    /// Distance from the coast to the shelf break
+    pub shelf_width: f64,
+    /// Depth of the water at the shelf break
+    pub shelf_depth: f64,
+    /// Width of the continental slope, from the shelf break to the abyssal plain
+    pub slope_width: f64,
+    /// Depth of the abyssal plain
+    pub abyss_depth: f64,
+    /// How much noise roughens the ocean floor, as a fraction of the local depth
+    pub roughness: f64,
+    /// Depth of the tops of reefs; reefs are disabled if `None`
+    pub reef_depth: Option<f64>,
+    /// Distance of reefs from the coast, as a fraction of the shelf width
+    pub reef_distance: f64,
+    /// How strongly shoals raise the shelf, as a fraction of its depth
+    pub shoals: f64,
+}
+
+impl Default for Bathymetry {
+    fn default() -> Self {
+        Self {
+            shelf_width: 24.0,
+            shelf_depth: 0.08,
+            slope_width: 30.0,
+            abyss_depth: 0.9,
+            roughness: 0.15,
+            reef_depth: Some(0.004),
+            reef_distance: 0.6,
+            shoals: 0.6,
+        }
+    }
+}
+
+impl Bathymetry {
+    /// Reshape every cell of open ocean
+    pub fn shape(&self, elevation: &mut Elevation, seed: u64) {
+        elevation.update_coast();
+        let distance = coast_distance(elevation);
+
+        let mut noise = FastNoise::seeded(seed);
+        noise.set_noise_type(NoiseType::SimplexFractal);
+        noise.set_fractal_type(FractalType::FBM);
+        noise.set_fractal_octaves(4);
+        noise.set_frequency(0.05);
+
+        let mut ridges = FastNoise::seeded(seed.wrapping_add(1));
+        ridges.set_noise_type(NoiseType::SimplexFractal);
+        ridges.set_fractal_type(FractalType::RigidMulti);
+        ridges.set_fractal_octaves(3);
+        ridges.set_frequency(0.04);
+
+        for idx in 0..elevation.len() {
+            if !elevation.ocean()[idx] {
+                continue;
+            }
+
+            let (x, y) = elevation.from_idx(idx);
+            let (fx, fy) = (x as f32, y as f32);
+            let dist = distance[idx];
+
+            let mut depth = self.depth_at(dist);
+            // Roughen the floor
+            depth *= 1.0 + self.roughness * f64::from(noise.get_noise(fx, fy));
+
+            if dist < self.shelf_width {
+                // Shoals rise out of the shelf where our noise peaks
+                let shoal = f64::from(noise.get_noise(fy, fx)).max(0.0);
+                depth *= 1.0 - self.shoals * shoal;
+
+                // Reefs follow a band around the coast, broken up by ridged noise
+                if let Some(reef_depth) = self.reef_depth {
+                    let band = (dist / self.shelf_width - self.reef_distance).abs() * 8.0;
+                    let reef = (f64::from(ridges.get_noise(fx, fy)) - band).max(0.0);
+                    depth -= (depth - reef_depth).max(0.0) * reef.min(1.0);
+                }
+            }
+
+            // Never rise above the surface; that would create new land
+            elevation[idx] = SEA_LEVEL - depth.max(0.0001);
+        }
+    }
+
+    /// Depth of the ocean floor at `dist` cells from the coast, before any noise
+    pub fn depth_at(&self, dist: f64) -> f64 {
+        if dist < self.shelf_width {
+            // The shelf slopes gently and evenly down to the shelf break
+            self.shelf_depth * dist / self.shelf_width
+        } else {
+            // The continental slope follows a smooth S-curve down to the abyssal plain
+            let t = ramp(
+                self.shelf_width,
+                self.shelf_width + self.slope_width.max(1.0),
+                dist,
+            );
+
+            self.shelf_depth + (self.abyss_depth - self.shelf_depth) * t
+        }
+    }
+}
+
+/// Find the distance of each cell from the nearest land, using a chamfer distance transform
+fn coast_distance(elevation: &Elevation) -> Grid<f64> {
+    let size = elevation.size();
+    let mut distance = elevation
+        .ocean()
+        .map(|&ocean| if ocean { f64::MAX } else { 0.0 });
+
+    // Sweep forwards then backwards, each time pulling in the distances of the neighbors we've
+    // already visited in that sweep
+    let forward = [
+        (-1, -1, SQRT_2),
+        (0, -1, 1.0),
+        (1, -1, SQRT_2),
+        (-1, 0, 1.0),
+    ];
+    let backward = [(1, 1, SQRT_2), (0, 1, 1.0), (-1, 1, SQRT_2), (1, 0, 1.0)];
+    let mut sweep = |x: u32, y: u32, offsets: &[(i64, i64, f64)]| {
+        let idx = distance.to_idx(x, y);
+        for &(dx, dy, cost) in offsets {
+            let (nx, ny) = (i64::from(x) + dx, i64::from(y) + dy);
+            if distance.in_bounds(nx, ny) {
+                let dist = distance[(nx as u32, ny as u32)] + cost;
+                if dist < distance[idx] {
+                    distance[idx] = dist;
+                }
+            }
+        }
+    };
+
+    for y in 0..size {
+        for x in 0..size {
+            sweep(x, y, &forward);
+        }
+    }
+    for y in (0..size).rev() {
+        for x in (0..size).rev() {
+            sweep(x, y, &backward);
+        }
+    }
+
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_profile() {
+        let bathymetry = Bathymetry::default();
+
+        assert_eq!(bathymetry.depth_at(0.0), 0.0);
+        assert!(
+            (bathymetry.depth_at(bathymetry.shelf_width) - bathymetry.shelf_depth).abs() < 1e-9
+        );
+        assert_eq!(bathymetry.depth_at(1000.0), bathymetry.abyss_depth);
+
+        // Depth only ever increases away from the coast, and the slope is steeper than the shelf
+        let mut prev = 0.0;
+        for dist in 1..100 {
+            let depth = bathymetry.depth_at(f64::from(dist));
+            assert!(depth >= prev);
+            prev = depth;
+        }
+        let shelf = bathymetry.depth_at(2.0) - bathymetry.depth_at(1.0);
+        let slope = bathymetry.depth_at(bathymetry.shelf_width + bathymetry.slope_width / 2.0)
+            - bathymetry.depth_at(bathymetry.shelf_width + bathymetry.slope_width / 2.0 - 1.0);
+        assert!(slope > shelf);
+    }
+
+    /// Land along the eastern edge of the map, with shallow sea out to the west
+    fn coast() -> Elevation {
+        Elevation::from(Grid::from_fn(100, |x, _| if x >= 90 { 0.1 } else { -0.01 }))
+    }
+
+    #[test]
+    fn shaped_floor_deepens_away_from_the_coast() {
+        let mut elevation = coast();
+        let before = elevation.clone();
+        let smooth = Bathymetry {
+            roughness: 0.0,
+            reef_depth: None,
+            shoals: 0.0,
+            ..Default::default()
+        };
+
+        smooth.shape(&mut elevation, 1);
+
+        // The land is left untouched
+        for ((x, y), &height) in before.cells() {
+            if height > SEA_LEVEL {
+                assert_eq!(elevation[(x, y)], height);
+            }
+        }
+
+        // Across the shelf, down the slope, and out onto the abyssal plain, the floor only falls
+        let depth = |x| SEA_LEVEL - elevation[(x, 50)];
+        for x in 0..89 {
+            assert!(depth(x) >= depth(x + 1), "rises at {}", x);
+        }
+        let shelf_break = 90 - smooth.shelf_width as u32;
+        assert!((depth(shelf_break) - smooth.shelf_depth).abs() < 1e-9);
+        assert_eq!(depth(0), smooth.abyss_depth);
+    }
+
+    #[test]
+    fn shaped_floor_stays_underwater() {
+        let mut elevation = coast();
+        let before = elevation.clone();
+
+        Bathymetry::default().shape(&mut elevation, 1);
+
+        for ((x, y), &height) in before.cells() {
+            if height > SEA_LEVEL {
+                assert_eq!(elevation[(x, y)], height);
+            } else {
+                assert!(elevation[(x, y)] < SEA_LEVEL);
+            }
+        }
+        assert!(elevation[(0, 50)] < -0.5);
+    }
+}
@@ -0,0 +1,287 @@
+//! Shape the coastline with waves
+//!
+//! Each stretch of coast is exposed to waves in proportion to its fetch, the distance of open
+//! water the wind can blow across before reaching it. Exposed headlands are worn back into sea
+//! cliffs, while the eroded material washes into sheltered bays and builds up beaches.
+
+use super::elevation::Elevation;
+use super::SEA_LEVEL;
+use std::f64::consts::TAU;
+
+/// Number of directions in which we measure fetch
+const FETCH_DIRECTIONS: u32 = 16;
+
+/// The kind of shore along a stretch of coast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shore {
+    /// Sand or shingle deposited in sheltered water
+    Beach,
+    /// Steep faces cut back by heavy waves
+    Cliff,
+    /// Anything in between
+    Rocky,
+}
+
+/// A single cell of coast and the shore found there
+#[derive(Debug, Clone, Copy)]
+pub struct CoastCell {
+    pub x: u32,
+    pub y: u32,
+    /// How exposed this cell is to waves, in the range [0.0, 1.0]
+    pub exposure: f64,
+    pub shore: Shore,
+}
+
+/// Parameters for shaping the coast
+#[derive(Debug, Clone)]
+pub struct Coastal {
+    /// Number of passes of wave erosion and deposition
+    pub iterations: u32,
+    /// Height worn away from a fully exposed cell in each pass
+    pub erosion_rate: f64,
+    /// Direction the prevailing waves come from, in radians; `None` for waves from all directions
+    pub wave_direction: Option<f64>,
+    /// Furthest distance we measure fetch, as a fraction of the map size
+    pub max_fetch: f64,
+    /// Exposure above which the coast becomes cliffs
+    pub cliff_exposure: f64,
+    /// Exposure below which the coast becomes beaches
+    pub beach_exposure: f64,
+    /// Height above sea level to which beaches are built up
+    pub beach_height: f64,
+}
+
+impl Default for Coastal {
+    fn default() -> Self {
+        Self {
+            iterations: 10,
+            erosion_rate: 0.002,
+            wave_direction: None,
+            max_fetch: 0.25,
+            cliff_exposure: 0.5,
+            beach_exposure: 0.25,
+            beach_height: 0.005,
+        }
+    }
+}
+
+impl Coastal {
+    /// Erode and build up the coast, returning the resulting shore at each coast cell
+    pub fn shape(&self, elevation: &mut Elevation) -> Vec<CoastCell> {
+        // Sediment our beaches have no room for waits for the next pass
+        let mut sediment = 0.0;
+        for _ in 0..self.iterations {
+            let coast = self.classify(elevation);
+
+            // Waves wear away exposed coast...
+            for cell in coast.iter().filter(|cell| cell.shore != Shore::Beach) {
+                let height = &mut elevation[(cell.x, cell.y)];
+                let eroded = (self.erosion_rate * cell.exposure).min(*height - SEA_LEVEL);
+                *height -= eroded;
+                sediment += eroded;
+            }
+
+            // ...and carry it into sheltered water, where it settles into beaches
+            let mut beaches: Vec<_> = coast
+                .iter()
+                .filter(|cell| cell.shore == Shore::Beach)
+                .flat_map(|cell| {
+                    let shelter = 1.0 - cell.exposure / self.beach_exposure;
+                    elevation
+                        .neighbors8(cell.x, cell.y)
+                        .chain(std::iter::once((cell.x, cell.y)))
+                        .map(move |neighbor| (neighbor, shelter))
+                        .collect::<Vec<_>>()
+                })
+                .filter(|&(neighbor, _)| elevation[neighbor] < SEA_LEVEL + self.beach_height)
+                .collect();
+            // Beaches only build up to `beach_height`, so whatever overflows a full beach is
+            // shared out again among those with room to spare
+            let top = SEA_LEVEL + self.beach_height;
+            while sediment > 0.0 {
+                let total: f64 = beaches.iter().map(|(_, shelter)| shelter).sum();
+                if total <= 0.0 {
+                    break;
+                }
+
+                let mut settled = 0.0;
+                for &(cell, shelter) in beaches.iter() {
+                    let height = &mut elevation[cell];
+                    let deposit = sediment * shelter / total;
+                    if *height + deposit >= top {
+                        settled += top - *height;
+                        *height = top;
+                    } else {
+                        *height += deposit;
+                        settled += deposit;
+                    }
+                }
+                sediment = (sediment - settled).max(0.0);
+
+                // Once nothing overflows, everything has settled
+                let before = beaches.len();
+                beaches.retain(|&(cell, _)| elevation[cell] < top);
+                if beaches.len() == before {
+                    break;
+                }
+            }
+
+            elevation.update_coast();
+        }
+
+        self.classify(elevation)
+    }
+
+    /// Classify every coast cell by its exposure to waves
+    pub fn classify(&self, elevation: &Elevation) -> Vec<CoastCell> {
+        elevation
+            .get_coast()
+            .iter()
+            .map(|&(x, y)| {
+                let exposure = self.exposure(elevation, x, y);
+                let shore = if exposure >= self.cliff_exposure {
+                    Shore::Cliff
+                } else if exposure <= self.beach_exposure {
+                    Shore::Beach
+                } else {
+                    Shore::Rocky
+                };
+
+                CoastCell {
+                    x,
+                    y,
+                    exposure,
+                    shore,
+                }
+            })
+            .collect()
+    }
+
+    /// Find the exposure of (x, y) to waves from its fetch in every direction
+    pub fn exposure(&self, elevation: &Elevation, x: u32, y: u32) -> f64 {
+        let max_fetch = (f64::from(elevation.size()) * self.max_fetch).max(1.0);
+
+        let mut exposure = 0.0;
+        let mut total_weight = 0.0;
+        for dir in 0..FETCH_DIRECTIONS {
+            let angle = f64::from(dir) / f64::from(FETCH_DIRECTIONS) * TAU;
+            // Waves from the prevailing direction count fully, waves against it not at all
+            let weight = match self.wave_direction {
+                Some(wave) => (angle - wave).cos().max(0.0),
+                None => 1.0,
+            };
+            if weight <= 0.0 {
+                continue;
+            }
+
+            exposure += weight * fetch(elevation, x, y, angle, max_fetch) / max_fetch;
+            total_weight += weight;
+        }
+
+        // At best half our directions can face the sea, so a straight coast facing open ocean is
+        // fully exposed
+        if total_weight > 0.0 {
+            (2.0 * exposure / total_weight).min(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Distance across open water from (x, y) in the direction `angle`, up to `max_fetch`
+///
+/// Water that runs off the edge of the map leads to open ocean, so gets the full `max_fetch`.
+fn fetch(elevation: &Elevation, x: u32, y: u32, angle: f64, max_fetch: f64) -> f64 {
+    let (dx, dy) = (angle.cos(), angle.sin());
+    let (x, y) = (f64::from(x), f64::from(y));
+
+    let mut dist = 1.0;
+    while dist < max_fetch {
+        let (cx, cy) = (
+            (x + dx * dist).round() as i64,
+            (y + dy * dist).round() as i64,
+        );
+        if !elevation.in_bounds(cx, cy) {
+            return max_fetch;
+        }
+        if elevation[(cx as u32, cy as u32)] > SEA_LEVEL {
+            // Ran aground, `dist` cells from where we started
+            return dist;
+        }
+
+        dist += 1.0;
+    }
+
+    max_fetch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::grid::Grid;
+
+    #[test]
+    fn bays_are_sheltered() {
+        // A square island with a narrow inlet cut into its southern side
+        let grid = Grid::from_fn(64, |x, y| {
+            let island = (20..44).contains(&x) && (20..44).contains(&y);
+            let inlet = (31..33).contains(&x) && (24..44).contains(&y);
+            if island && !inlet {
+                0.5
+            } else {
+                -0.5
+            }
+        });
+        let mut elevation = Elevation::from(grid);
+        elevation.update_coast();
+
+        let coastal = Coastal::default();
+        let coast = coastal.classify(&elevation);
+        let find = |x, y| *coast.iter().find(|c| c.x == x && c.y == y).unwrap();
+
+        let headland = find(20, 20);
+        let inlet = find(30, 26);
+        assert!(headland.exposure > inlet.exposure);
+        assert_eq!(headland.shore, Shore::Cliff);
+        assert_eq!(inlet.shore, Shore::Beach);
+    }
+
+    #[test]
+    fn beaches_keep_what_they_cannot_hold() {
+        // The same island, but with an inlet that shoals towards its mouth, where it soon fills up
+        let grid = Grid::from_fn(64, |x, y| {
+            let island = (20..44).contains(&x) && (20..44).contains(&y);
+            let inlet = (31..33).contains(&x) && (24..44).contains(&y);
+            if inlet {
+                if y < 36 {
+                    SEA_LEVEL - 0.2
+                } else {
+                    SEA_LEVEL - 0.001
+                }
+            } else if island {
+                0.5
+            } else {
+                -0.5
+            }
+        });
+        let mut elevation = Elevation::from(grid);
+        elevation.update_coast();
+        let before: f64 = elevation.iter().sum();
+
+        let coastal = Coastal {
+            iterations: 1,
+            erosion_rate: 0.02,
+            ..Default::default()
+        };
+        coastal.shape(&mut elevation);
+
+        // Everything worn from the cliffs has settled somewhere, without overfilling any beach
+        let after: f64 = elevation.iter().sum();
+        assert!((after - before).abs() < 1e-9, "{} != {}", after, before);
+        for x in 31..33 {
+            for y in 24..44 {
+                assert!(elevation[(x, y)] <= SEA_LEVEL + coastal.beach_height + 1e-12);
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Route water over the terrain to the ocean
+//!
+//! Every land cell is assigned a single receiver, the neighbor its water flows to, forming a
+//! drainage tree rooted in the ocean. Routing uses a priority flood, so water escapes pits and
+//! flats by the lowest available path instead of getting stuck.
+//!
+//! See Barnes, Lehman, and Mulla's "Priority-Flood: An Optimal Depression-Filling and
+//! Watershed-Labeling Algorithm for Digital Elevation Models" (2014).
+
+use super::elevation::Elevation;
+use super::grid::Grid;
+use super::SEA_LEVEL;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// A cell waiting in a priority flood's queue
+///
+/// Ordered so that a `BinaryHeap` pops the lowest first, and among equal heights the lowest index.
+#[derive(Debug, PartialEq)]
+pub struct Queued {
+    pub height: f64,
+    pub idx: usize,
+}
+
+impl Eq for Queued {}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap, but we want the lowest cell first
+        other
+            .height
+            .partial_cmp(&self.height)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.idx.cmp(&self.idx))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug)]
+pub struct Drainage {
+    /// The cell each cell drains into, or `None` for the ocean (and off the edge of the map)
+    receivers: Grid<Option<usize>>,
+    /// Every land cell, ordered so that each cell comes after the cell it drains into
+    stack: Vec<usize>,
+    /// Number of cells draining through each cell, including itself
+    area: Grid<f64>,
+}
+
+#[allow(dead_code)]
+impl Drainage {
+    pub fn new(elevation: &Elevation) -> Self {
+        let size = elevation.size();
+        let mut receivers = Grid::new(size, None);
+        let mut stack = Vec::new();
+        let mut visited = Grid::new(size, false);
+        let mut queue = BinaryHeap::new();
+
+        // The ocean is where all water ends up, so that's where we start our flood
+        for (idx, &height) in elevation.iter().enumerate() {
+            if height <= SEA_LEVEL {
+                visited[idx] = true;
+                queue.push(Queued { height, idx });
+            }
+        }
+
+        while let Some(Queued { height, idx }) = queue.pop() {
+            let (x, y) = elevation.from_idx(idx);
+            for (nx, ny) in elevation.neighbors8(x, y) {
+                let neighbor = elevation.to_idx(nx, ny);
+                if visited[neighbor] {
+                    continue;
+                }
+                visited[neighbor] = true;
+
+                // Our neighbor drains through us; if it's lower than us it's in a pit, so raise
+                // it to our level to let the flood find the pit's outlet
+                receivers[neighbor] = Some(idx);
+                stack.push(neighbor);
+                queue.push(Queued {
+                    height: elevation[neighbor].max(height),
+                    idx: neighbor,
+                });
+            }
+        }
+
+        // Accumulate drainage area from the top of each tree down to the ocean
+        let mut area = Grid::new(size, 1.0);
+        for &idx in stack.iter().rev() {
+            if let Some(receiver) = receivers[idx] {
+                area[receiver] += area[idx];
+            }
+        }
+
+        Self {
+            receivers,
+            stack,
+            area,
+        }
+    }
+
+    /// The cell that `idx` drains into, or `None` if it's in the ocean
+    pub fn receiver(&self, idx: usize) -> Option<usize> {
+        self.receivers[idx]
+    }
+
+    /// Land cells ordered from the ocean upstream; every cell comes after its receiver
+    pub fn stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    /// Number of cells draining through each cell, including itself
+    pub fn area(&self) -> &Grid<f64> {
+        &self.area
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drains_to_the_ocean() {
+        // A cone with a pit in its side, surrounded by ocean
+        let mut grid = Grid::from_fn(16, |x, y| {
+            let (dx, dy) = (f64::from(x) - 7.5, f64::from(y) - 7.5);
+            0.5 - (dx * dx + dy * dy).sqrt() / 12.0
+        });
+        grid[(5, 7)] = 0.01;
+        let elevation = Elevation::from(grid);
+        let drainage = Drainage::new(&elevation);
+
+        let land = elevation.iter().filter(|&&h| h > SEA_LEVEL).count();
+        assert_eq!(drainage.stack().len(), land);
+
+        // Every cell comes after the cell it drains into
+        let mut seen = Grid::new(16, false);
+        for &idx in drainage.stack() {
+            let receiver = drainage.receiver(idx).unwrap();
+            assert!(seen[receiver] || elevation[receiver] <= SEA_LEVEL);
+            seen[idx] = true;
+        }
+
+        // All the land drains into the ocean
+        let total: f64 = elevation
+            .iter()
+            .zip(drainage.area().iter())
+            .filter(|(&h, _)| h <= SEA_LEVEL)
+            .map(|(_, &area)| area - 1.0)
+            .sum();
+        assert_eq!(total as usize, land);
+    }
+}
@@ -1,51 +1,47 @@
-use super::gradient::Gradient;
-use bracket_noise::prelude::*;
+use super::grid::Grid;
+use super::noise::{EmptyCombinator, Noise};
+use super::shape::IslandShape;
+use super::spine::Spine;
 use nalgebra as na;
 use rand::prelude::*;
 use rand_xoshiro::Xoshiro256StarStar;
-use std::ops::{Index, IndexMut};
+use std::ops::{Deref, DerefMut};
 
 pub type Height = f64;
 
-const HEIGHT_SCALE: f64 = 40.0;
+/// Vertical scale of our heights relative to the width of a cell
+pub const HEIGHT_SCALE: f64 = 40.0;
 
 pub struct Elevation {
-    elevation: Vec<Height>,
+    elevation: Grid<Height>,
     coast: Vec<(u32, u32)>,
-    size: u32,
+    /// Cells connected to the open ocean, as opposed to inland lakes
+    ocean: Grid<bool>,
 }
 
 impl Elevation {
-    pub fn new(rng: &mut Xoshiro256StarStar, size: u32) -> Self {
-        // Our gradient helps define our overall island shape
-        let gradient = Gradient::new(rng, 4);
-
+    pub fn new(
+        rng: &mut Xoshiro256StarStar,
+        size: u32,
+        shape: &dyn IslandShape,
+        noise: &Noise,
+        spines: &[Spine],
+    ) -> Result<Self, EmptyCombinator> {
         // Noise gives us natural-looking terrain
-        // I have no idea what these parameters do!
-        // They're stolen directly from https://github.com/amethyst/bracket-lib/blob/master/bracket-noise/examples/simplex_fractal.rs
-        // They do seem to give me results I like, though!
-        let mut noise = FastNoise::seeded(rng.gen());
-        noise.set_noise_type(NoiseType::SimplexFractal);
-        noise.set_fractal_type(FractalType::FBM);
-        noise.set_fractal_octaves(5);
-        noise.set_fractal_gain(0.6);
-        noise.set_fractal_lacunarity(2.0);
-        noise.set_frequency(2.0);
-
-        // A closure to allow us to easily use noise+gradient to calculate the base height
-        let raw_height = move |x: f64, y: f64| -> f64 {
-            // Get a noise value, and "pull" it up
-            let mut noise = noise.get_noise(x as f32, y as f32) as f64;
-            noise = (noise + 0.5) / 2.0;
+        let noise = noise.build(rng.gen())?;
+        // Mountain ranges give our island its backbone
+        let ranges: Vec<_> = spines.iter().map(|spine| spine.build(rng.gen())).collect();
 
-            // Add our gradient
-            noise + gradient.at(x, y)
+        // A closure to allow us to easily use noise+shape to calculate the base height
+        let raw_height = move |x: f64, y: f64| -> f64 {
+            // Add our island's shape and mountain ranges to our noise
+            noise.get(x, y) + shape.at(x, y) + ranges.iter().map(|r| r.at(x, y)).sum::<f64>()
         };
 
         let mut elevation = Elevation {
-            elevation: vec![0.0; (size * size) as usize],
+            elevation: Grid::new(size, 0.0),
             coast: Vec::new(),
-            size,
+            ocean: Grid::new(size, false),
         };
 
         // Scale our (x, y) by our size
@@ -54,7 +50,7 @@ impl Elevation {
         // Compute sea level to ensure a water border
         let sea_level = {
             // Establish the width of our water border
-            let perimeter = 30;
+            let perimeter = 30.min(size / 2);
             // Initialize sea level to a point we know will be within our border
             let mut sea_level = raw_height(0.0, 0.0);
 
@@ -118,31 +114,7 @@ impl Elevation {
         }
 
         // Find the coast
-        let mut ocean = vec![false; elevation.elevation.len()];
-        elevation.coast = {
-            let mut coast = Vec::with_capacity((size * size / 4) as usize);
-            let mut active = vec![(0, 0)];
-
-            while let Some((x, y)) = active.pop() {
-                for (x, y) in elevation.get_neighbors(x, y) {
-                    let idx = elevation.to_idx(x, y);
-                    if ocean[idx] {
-                        continue;
-                    }
-
-                    ocean[idx] = true;
-
-                    if elevation[idx] > sea_level {
-                        coast.push((x, y));
-                    } else {
-                        active.push((x, y));
-                    }
-                }
-            }
-
-            coast.shrink_to_fit();
-            coast
-        };
+        let ocean = elevation.find_coast(sea_level);
 
         // Perform a breadth-first search to rescale heights based on distance from the coast
         // By using our ocean as the initial value for visited points we can restrict this to land
@@ -152,7 +124,7 @@ impl Elevation {
         let mut max_elev = 0.0; // Find the max height for the second rescale pass
         while !frontier.is_empty() {
             while let Some((x, y)) = frontier.pop() {
-                for (x, y) in elevation.get_neighbors(x, y) {
+                for (x, y) in elevation.neighbors8(x, y) {
                     let idx = elevation.to_idx(x, y);
                     if visited[idx] {
                         continue;
@@ -163,7 +135,9 @@ impl Elevation {
                     let d = {
                         let d = elevation.coast.iter().fold(u32::MAX, |min, coast| {
                             // Using distance squared here to avoid costly square root at this stage
-                            let d = (x - coast.0).pow(2) + (y - coast.1).pow(2);
+                            let dx = i64::from(x) - i64::from(coast.0);
+                            let dy = i64::from(y) - i64::from(coast.1);
+                            let d = (dx.pow(2) + dy.pow(2)) as u32;
                             if d < min {
                                 d
                             } else {
@@ -212,7 +186,7 @@ impl Elevation {
             let mut shore: f64 = 100.0;
 
             while let Some((x, y)) = active.pop() {
-                for (x, y) in elevation.get_neighbors(x, y) {
+                for (x, y) in elevation.neighbors8(x, y) {
                     // Make sure we haven't already visited this one
                     let idx = elevation.to_idx(x, y);
                     if visited[idx] {
@@ -246,41 +220,7 @@ impl Elevation {
             }
         }
 
-        elevation
-    }
-
-    fn get_neighbors(&self, x: u32, y: u32) -> impl Iterator<Item = (u32, u32)> {
-        let size = self.size;
-
-        vec![
-            (x.wrapping_sub(1), y.wrapping_sub(1)),
-            (x.wrapping_sub(1), y),
-            (x.wrapping_sub(1), y.wrapping_add(1)),
-            (x, y.wrapping_sub(1)),
-            (x, y.wrapping_add(1)),
-            (x.wrapping_add(1), y.wrapping_sub(1)),
-            (x.wrapping_add(1), y),
-            (x.wrapping_add(1), y.wrapping_add(1)),
-        ]
-        .into_iter()
-        .filter(move |(x, y)| *x < size && *y < size)
-    }
-
-    #[inline(always)]
-    pub fn to_idx(&self, x: u32, y: u32) -> usize {
-        (x + y * self.size) as usize
-    }
-
-    #[inline(always)]
-    pub fn from_idx(&self, idx: usize) -> (u32, u32) {
-        let idx = idx as u32;
-
-        (idx % self.size, idx / self.size)
-    }
-
-    #[inline(always)]
-    pub fn size(&self) -> u32 {
-        self.size
+        Ok(elevation)
     }
 
     pub fn get_normal(&self, x: u32, y: u32) -> na::Vector3<f64> {
@@ -301,76 +241,199 @@ impl Elevation {
         na::Vector3::new(rl * HEIGHT_SCALE, bt * HEIGHT_SCALE, -2.0).normalize()
     }
 
-    pub fn _iter(&self) -> impl Iterator<Item = &f64> {
-        self.elevation.iter()
+    /// Sample the elevation at any point using bilinear interpolation
+    pub fn sample(&self, x: f64, y: f64) -> Height {
+        self.elevation.sample_bilinear(x, y)
     }
 
-    pub fn get_coast<'a>(&'a self) -> &'a Vec<(u32, u32)> {
-        &self.coast
+    /// Sample the elevation at any point using bicubic interpolation
+    ///
+    /// Smoother than `sample`, but more expensive.
+    #[allow(dead_code)]
+    pub fn sample_bicubic(&self, x: f64, y: f64) -> Height {
+        self.elevation.sample_bicubic(x, y)
     }
-}
 
-impl Index<usize> for Elevation {
-    type Output = Height;
+    /// Get the gradient of the terrain at any point, interpolated between cells
+    ///
+    /// The gradient points uphill; its magnitude is the change in height per cell.
+    /// Interpolation follows Hans Theobald Beyer's "Implementation of a method for hydraulic
+    /// erosion" (2015).
+    ///
+    /// A map of a single cell has nothing to interpolate between, and so is flat.
+    pub fn gradient_at(&self, x: f64, y: f64) -> na::Vector2<f64> {
+        if self.size() < 2 {
+            return na::Vector2::zeros();
+        }
 
-    fn index(&self, idx: usize) -> &Self::Output {
-        &self.elevation[idx]
+        // Stay on the map, but let the last cell interpolate from the one before it, with u or v
+        // reaching 1.0 on the far edge
+        let (max, last) = (f64::from(self.size() - 1), f64::from(self.size() - 2));
+        let (x, y) = (x.clamp(0.0, max), y.clamp(0.0, max));
+        let (x0, y0) = (x.floor().min(last), y.floor().min(last));
+        let (u, v) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as u32, y0 as u32);
+
+        let nw = self[(x0, y0)];
+        let ne = self[(x0 + 1, y0)];
+        let sw = self[(x0, y0 + 1)];
+        let se = self[(x0 + 1, y0 + 1)];
+
+        na::Vector2::new(
+            (ne - nw) * (1.0 - v) + (se - sw) * v,
+            (sw - nw) * (1.0 - u) + (se - ne) * u,
+        )
     }
-}
 
-impl Index<(u32, u32)> for Elevation {
-    type Output = Height;
+    /// Get the surface normal at any point, interpolated between cells
+    ///
+    /// Like `get_normal`, but built from `gradient_at` so it varies smoothly within a cell.
+    pub fn normal_at(&self, x: f64, y: f64) -> na::Vector3<f64> {
+        // Central differencing in get_normal spans two cells, so double our per-cell gradient to
+        // keep the two normals comparable
+        let gradient = self.gradient_at(x, y) * -2.0;
 
-    fn index(&self, key: (u32, u32)) -> &Self::Output {
-        assert!(
-            key.0 < self.size,
-            "X coordinate is out of bounds! {:?}",
-            key
-        );
-        assert!(
-            key.1 < self.size,
-            "Y coordinate is out of bounds! {:?}",
-            key
-        );
+        na::Vector3::new(gradient.x * HEIGHT_SCALE, gradient.y * HEIGHT_SCALE, -2.0).normalize()
+    }
+
+    /// Flood-fill the ocean from the corner of the map to find the coast
+    ///
+    /// Returns which cells were visited by the flood: the ocean, plus the land cells touching it
+    /// that make up our coast.
+    fn find_coast(&mut self, sea_level: Height) -> Vec<bool> {
+        let size = self.size();
+        let mut ocean = vec![false; self.len()];
+        self.coast = {
+            let mut coast = Vec::with_capacity((size * size / 4) as usize);
+            let mut active = vec![(0, 0)];
 
-        &self[self.to_idx(key.0, key.1)]
+            while let Some((x, y)) = active.pop() {
+                for (x, y) in self.neighbors8(x, y) {
+                    let idx = self.to_idx(x, y);
+                    if ocean[idx] {
+                        continue;
+                    }
+
+                    ocean[idx] = true;
+
+                    if self[idx] > sea_level {
+                        coast.push((x, y));
+                    } else {
+                        active.push((x, y));
+                    }
+                }
+            }
+
+            coast.shrink_to_fit();
+            coast
+        };
+        self.ocean = Grid::from_fn(self.size(), |x, y| {
+            let idx = self.to_idx(x, y);
+            ocean[idx] && self[idx] <= sea_level
+        });
+
+        ocean
+    }
+
+    /// Find the coast again after the terrain has changed
+    pub fn update_coast(&mut self) {
+        self.find_coast(super::SEA_LEVEL);
+    }
+
+    /// Whether each cell is part of the open ocean, as of the last time we found the coast
+    pub fn ocean(&self) -> &Grid<bool> {
+        &self.ocean
+    }
+
+    pub fn get_coast(&self) -> &Vec<(u32, u32)> {
+        &self.coast
     }
 }
 
-impl IndexMut<usize> for Elevation {
-    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
-        &mut self.elevation[idx]
+#[cfg(test)]
+impl From<Grid<Height>> for Elevation {
+    fn from(elevation: Grid<Height>) -> Self {
+        let size = elevation.size();
+
+        Self {
+            elevation,
+            coast: Vec::new(),
+            ocean: Grid::new(size, false),
+        }
     }
 }
 
-impl IndexMut<(u32, u32)> for Elevation {
-    fn index_mut(&mut self, key: (u32, u32)) -> &mut Self::Output {
-        assert!(
-            key.0 < self.size,
-            "X coordinate is out of bounds! {:?}",
-            key
-        );
-        assert!(
-            key.1 < self.size,
-            "Y coordinate is out of bounds! {:?}",
-            key
-        );
+impl Deref for Elevation {
+    type Target = Grid<Height>;
 
-        let idx = self.to_idx(key.0, key.1);
+    fn deref(&self) -> &Self::Target {
+        &self.elevation
+    }
+}
 
-        &mut self[idx]
+impl DerefMut for Elevation {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.elevation
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::map::shape::ShapeKind;
+
+    #[test]
+    fn tiny_maps_are_flat() {
+        for size in 0..2 {
+            let elevation = Elevation::from(Grid::new(size, 0.5));
+            assert_eq!(elevation.gradient_at(0.0, 0.0), na::Vector2::zeros());
+        }
+    }
+
+    #[test]
+    fn gradients_interpolate_to_the_far_edge() {
+        let size = 10;
+        let ramp = Elevation::from(Grid::from_fn(size, |x, y| {
+            0.1 * f64::from(x) + 0.02 * f64::from(y)
+        }));
+        // A bilinear surface, whose gradient at (x, y) is exactly (y, x)
+        let saddle = Elevation::from(Grid::from_fn(size, |x, y| f64::from(x) * f64::from(y)));
+
+        let far = f64::from(size - 1);
+        for &(x, y) in [(0.0, 0.0), (3.25, 6.5), (far, 4.5), (2.5, far), (far, far)].iter() {
+            let gradient = ramp.gradient_at(x, y);
+            assert!((gradient.x - 0.1).abs() < 1e-9 && (gradient.y - 0.02).abs() < 1e-9);
+
+            let gradient = saddle.gradient_at(x, y);
+            assert!(
+                (gradient.x - y).abs() < 1e-9,
+                "{:?} at ({}, {})",
+                gradient,
+                x,
+                y
+            );
+            assert!(
+                (gradient.y - x).abs() < 1e-9,
+                "{:?} at ({}, {})",
+                gradient,
+                x,
+                y
+            );
+        }
+
+        // Beyond the edge, we take the gradient at the edge
+        assert_eq!(
+            saddle.gradient_at(far + 3.0, -2.0),
+            saddle.gradient_at(far, 0.0)
+        );
+    }
 
     #[test]
     fn to_and_from_idx() {
         let mut rng = Xoshiro256StarStar::seed_from_u64(1337);
         let size = 20;
-        let elev = Elevation::new(&mut rng, size);
+        let shape = ShapeKind::Gradient.generate(&mut rng);
+        let elev = Elevation::new(&mut rng, size, shape.as_ref(), &Noise::default(), &[]).unwrap();
 
         for x in 0..size {
             for y in 0..size {
@@ -2,8 +2,16 @@
 //!
 //! This is based on the method described at
 //! https://nickmcd.me/2020/04/10/simple-particle-based-hydraulic-erosion/
+//!
+//! Droplets move continuously across the map rather than from cell to cell, so heights and
+//! normals are interpolated at their position and erosion and deposition are shared between the
+//! four surrounding cells, as in Hans Theobald Beyer's "Implementation of a method for hydraulic
+//! erosion" (2015).
+//!
+//! Eroded material is taken from every cell within the radius of a `Brush` rather than from a
+//! single cell, which avoids carving needle-like pits; deposition remains bilinear.
 
-use super::{elevation::Elevation, SEA_LEVEL};
+use super::{elevation::Elevation, grid::Grid, SEA_LEVEL};
 use nalgebra as na;
 use rand::{distributions::Uniform, prelude::*};
 use rand_xoshiro::Xoshiro256StarStar;
@@ -23,6 +31,75 @@ const FRICTION: f64 = 0.05;
 const EVAP_RATE: f64 = 0.001;
 /// Rate of deposition of sediment
 const DEPOSITION_RATE: f64 = 0.1;
+/// Default radius of the erosion brush, in cells
+pub const BRUSH_RADIUS: u32 = 3;
+
+/// A weighted kernel used to spread erosion across neighboring cells
+#[derive(Debug, Clone)]
+pub struct Brush {
+    /// Offsets from the center cell and their weights; the weights sum to 1
+    weights: Vec<(i64, i64, f64)>,
+}
+
+impl Brush {
+    /// Create a new brush of the given radius
+    ///
+    /// Cells are weighted linearly by their distance from the center, falling to 0 at `radius`.
+    /// A radius of 0 gives a brush that erodes only the center cell.
+    pub fn new(radius: u32) -> Self {
+        let r = i64::from(radius);
+        let radius = f64::from(radius);
+
+        let mut weights = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let dist = ((dx * dx + dy * dy) as f64).sqrt();
+                if dist <= radius {
+                    weights.push((dx, dy, (radius - dist).max(0.0)));
+                }
+            }
+        }
+
+        let total: f64 = weights.iter().map(|w| w.2).sum();
+        if total > 0.0 {
+            for weight in weights.iter_mut() {
+                weight.2 /= total;
+            }
+        } else {
+            // Only possible with a radius of 0, which leaves just the center cell
+            weights[0].2 = 1.0;
+        }
+
+        Self { weights }
+    }
+
+    /// Remove `amount` of material from the cells around the cell containing (x, y)
+    ///
+    /// Near the edge of the map, the part of the brush that's still on the map takes all of it.
+    fn erode(&self, elevation: &mut Elevation, x: f64, y: f64, amount: f64) {
+        let (x, y) = (x.floor() as i64, y.floor() as i64);
+        let cells: Vec<_> = self
+            .weights
+            .iter()
+            .map(|&(dx, dy, weight)| (x + dx, y + dy, weight))
+            .filter(|&(x, y, _)| elevation.in_bounds(x, y))
+            .collect();
+
+        let total: f64 = cells.iter().map(|c| c.2).sum();
+        if total <= 0.0 {
+            return;
+        }
+        for (x, y, weight) in cells {
+            elevation[(x as u32, y as u32)] -= amount * weight / total;
+        }
+    }
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self::new(BRUSH_RADIUS)
+    }
+}
 
 #[derive(Debug)]
 struct Droplet {
@@ -42,25 +119,29 @@ impl Droplet {
         }
     }
 
-    fn ipos(&self) -> (u32, u32) {
-        (self.position[0] as u32, self.position[1] as u32)
-    }
-
-    fn descend(&mut self, elevation: &mut Elevation) {
+    /// Run our droplet downhill until it evaporates or reaches the sea, adding the sediment it
+    /// carries into the sea to `discharge`
+    ///
+    /// Some of that sediment settles straight onto the seabed where the droplet ends; only what's
+    /// left over is added to `discharge`, so no sediment is counted twice.
+    fn descend(&mut self, elevation: &mut Elevation, brush: &Brush, discharge: &mut Grid<f64>) {
         while self.volume > MIN_VOLUME {
-            // Floor the position to find the "cell" the droplet is in
-            let ipos = self.ipos();
+            let (x, y) = (self.position.x, self.position.y);
+            let height = elevation.sample(x, y);
 
             // Remove our droplet if it's reached the ocean
-            if elevation[ipos] < SEA_LEVEL {
-                // Deposit all remaining sediment here
-                elevation[ipos] += DT * self.volume * DEPOSITION_RATE * self.sediment;
+            if height < SEA_LEVEL {
+                // Drop some of our sediment here, and carry the rest out to sea
+                let carried = self.volume * self.sediment;
+                let deposited = DT * DEPOSITION_RATE * carried;
+                elevation.add_bilinear(x, y, deposited);
+                discharge[(x as u32, y as u32)] += carried - deposited;
 
                 break;
             }
 
             // Get the surface normal to accelerate our droplet
-            let normal = elevation.get_normal(ipos.0, ipos.1).xy();
+            let normal = elevation.normal_at(x, y).xy();
 
             // Newtonian Mechanics
             // Accelerate the droplet; F=ma, therefore a=F/m; m=volume*density
@@ -87,14 +168,21 @@ impl Droplet {
             let c_eq = {
                 let c_eq = self.volume
                     * self.velocity.magnitude()
-                    * (elevation[ipos] - elevation[self.ipos()]);
+                    * (height - elevation.sample(self.position.x, self.position.y));
                 c_eq.max(0.0)
             };
             // Compute the driving force (capacity difference)
             let c_diff = c_eq - self.sediment;
-            // Now perform the mass transfer
+            // Now perform the mass transfer at our old position
             self.sediment += DT * DEPOSITION_RATE * c_diff;
-            elevation[ipos] -= DT * self.volume * DEPOSITION_RATE * c_diff;
+            let amount = DT * self.volume * DEPOSITION_RATE * c_diff;
+            if amount > 0.0 {
+                // Erosion is spread across the brush...
+                brush.erode(elevation, x, y, amount);
+            } else {
+                // ...while deposition goes to the four surrounding cells
+                elevation.add_bilinear(x, y, -amount);
+            }
 
             // Evaporation
             self.volume *= 1.0 - DT * EVAP_RATE;
@@ -103,7 +191,16 @@ impl Droplet {
     }
 }
 
-pub fn erode(elevation: &mut Elevation, rng: &mut Xoshiro256StarStar, cycles: u32) {
+/// Run `cycles` droplets over the terrain
+///
+/// The sediment each droplet carries into the sea is added to `discharge` where it got there.
+pub fn erode(
+    elevation: &mut Elevation,
+    rng: &mut Xoshiro256StarStar,
+    cycles: u32,
+    brush: &Brush,
+    discharge: &mut Grid<f64>,
+) {
     let range = Uniform::new(0, elevation.size());
 
     for _ in 0..cycles {
@@ -117,6 +214,49 @@ pub fn erode(elevation: &mut Elevation, rng: &mut Xoshiro256StarStar, cycles: u3
             }
         };
         let mut drop = Droplet::new(pos);
-        drop.descend(elevation);
+        drop.descend(elevation, brush, discharge);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brush_weights() {
+        let brush = Brush::new(0);
+        assert_eq!(brush.weights, vec![(0, 0, 1.0)]);
+
+        let brush = Brush::new(3);
+        let total: f64 = brush.weights.iter().map(|w| w.2).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        // The center cell carries the most weight
+        let center = brush.weights.iter().find(|w| w.0 == 0 && w.1 == 0).unwrap();
+        assert!(brush.weights.iter().all(|w| w.2 <= center.2));
+    }
+
+    #[test]
+    fn brushes_erode_the_full_amount() {
+        let brush = Brush::new(3);
+        for &(x, y) in [(10.7, 10.2), (0.3, 0.9), (19.9, 0.0)].iter() {
+            let mut elevation = Elevation::from(Grid::new(20, 1.0));
+            brush.erode(&mut elevation, x, y, 0.5);
+
+            // Even where the brush hangs off the map, everything comes off what's left of it...
+            let removed: f64 = elevation.iter().map(|h| 1.0 - h).sum();
+            assert!(
+                (removed - 0.5).abs() < 1e-9,
+                "{} at ({}, {})",
+                removed,
+                x,
+                y
+            );
+
+            // ...centred on the cell the droplet is in
+            let (cx, cy) = (x as u32, y as u32);
+            let deepest = elevation.iter().cloned().fold(f64::INFINITY, f64::min);
+            assert_eq!(elevation[(cx, cy)], deepest);
+        }
     }
 }
@@ -0,0 +1,197 @@
+//! Find waterfalls and rapids along our rivers
+//!
+//! A waterfall is where a river drops a long way over a short distance, while rapids are where it
+//! runs down a long, steep stretch of its bed. Both make natural points of interest.
+
+use super::elevation::Elevation;
+use super::watershed::river::NodeId;
+use super::watershed::Watershed;
+use super::SEA_LEVEL;
+use std::f64::consts::SQRT_2;
+
+/// Distance, in cells, above a drop over which we measure how steeply the river was already
+/// falling
+const APPROACH: f64 = 5.0;
+
+/// The kind of drop along a river
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallKind {
+    Waterfall,
+    Rapids,
+}
+
+/// A waterfall or rapids along one of our rivers
+#[derive(Debug, Clone, Copy)]
+pub struct Fall {
+    /// The top of the drop
+    pub x: u32,
+    pub y: u32,
+    pub kind: FallKind,
+    /// Height the river drops from top to bottom
+    pub height: f64,
+    /// Distance, in cells, the river covers as it drops
+    pub length: f64,
+    /// Index of the watershed whose river drops here
+    pub watershed: usize,
+    /// The node at the top of the drop within the watershed's river
+    pub node: NodeId,
+}
+
+/// Parameters for finding waterfalls and rapids
+#[derive(Debug, Clone)]
+pub struct Falls {
+    /// Smallest drop that makes a waterfall
+    pub waterfall_height: f64,
+    /// Longest distance, in cells, over which a river can drop to make a waterfall
+    pub waterfall_run: f64,
+    /// How many times steeper than the river above it a drop must be to make a waterfall, rather
+    /// than just the continuation of a steep stretch
+    pub waterfall_contrast: f64,
+    /// Slope of the terrain, in degrees, above which a river runs through rapids
+    pub rapids_angle: f64,
+    /// Shortest distance, in cells, of steep river that makes rapids
+    pub rapids_length: f64,
+}
+
+impl Default for Falls {
+    fn default() -> Self {
+        Self {
+            waterfall_height: 0.02,
+            waterfall_run: 2.0,
+            waterfall_contrast: 2.0,
+            rapids_angle: 15.0,
+            rapids_length: 6.0,
+        }
+    }
+}
+
+impl Falls {
+    /// Find every waterfall and rapids along the rivers of `watersheds`
+    pub fn find(&self, elevation: &Elevation, watersheds: &[Watershed]) -> Vec<Fall> {
+        let steep = self.rapids_angle.to_radians().cos();
+        let mut falls = Vec::new();
+
+        for (watershed, river) in watersheds
+            .iter()
+            .enumerate()
+            .filter_map(|(i, watershed)| Some((i, watershed.river()?)))
+        {
+            for mut stem in river.stems() {
+                // Walk each stem downstream, with the distance travelled to each of its nodes
+                stem.reverse();
+                let cells: Vec<_> = stem
+                    .iter()
+                    .map(|&id| elevation.from_idx(river.node(id).idx))
+                    .collect();
+                // Rivers fall only as far as the sea's surface, however deep the water beyond
+                let heights: Vec<_> = cells
+                    .iter()
+                    .map(|&cell| elevation[cell].max(SEA_LEVEL))
+                    .collect();
+                let mut travelled = vec![0.0];
+                for pair in cells.windows(2) {
+                    let diagonal = pair[0].0 != pair[1].0 && pair[0].1 != pair[1].1;
+                    let step = if diagonal { SQRT_2 } else { 1.0 };
+                    travelled.push(travelled[travelled.len() - 1] + step);
+                }
+                let fall = |kind, top: usize, bottom: usize| Fall {
+                    x: cells[top].0,
+                    y: cells[top].1,
+                    kind,
+                    height: heights[top] - heights[bottom],
+                    length: travelled[bottom] - travelled[top],
+                    watershed,
+                    node: stem[top],
+                };
+                let is_waterfall = |top: usize, bottom: usize| {
+                    let drop = heights[top] - heights[bottom];
+                    let slope = drop / (travelled[bottom] - travelled[top]);
+
+                    // Compare with how steeply the river was already falling above the drop
+                    let above = (0..top)
+                        .rev()
+                        .find(|&k| travelled[top] - travelled[k] >= APPROACH)
+                        .unwrap_or(0);
+                    let approach = if above < top {
+                        (heights[above] - heights[top]) / (travelled[top] - travelled[above])
+                    } else {
+                        0.0
+                    };
+
+                    drop >= self.waterfall_height && slope >= approach * self.waterfall_contrast
+                };
+                // Rapids must be long enough, and fall at least as far as a waterfall; rivers can
+                // run along the foot of steep slopes without dropping much themselves
+                let rapids_to = |top: usize, bottom: usize| {
+                    let drop = heights[top] - heights[bottom];
+                    let length = travelled[bottom] - travelled[top];
+                    (length >= self.rapids_length && drop >= self.waterfall_height)
+                        .then(|| fall(FallKind::Rapids, top, bottom))
+                };
+
+                // Where the river runs steeply, and where that stretch began
+                let mut rapids: Option<usize> = None;
+                let mut i = 0;
+                while i < cells.len() {
+                    // Find the biggest drop within a short run downstream
+                    let bottom = (i + 1..cells.len())
+                        .take_while(|&j| travelled[j] - travelled[i] <= self.waterfall_run)
+                        .min_by(|&a, &b| heights[a].total_cmp(&heights[b]));
+                    if let Some(bottom) = bottom.filter(|&bottom| is_waterfall(i, bottom)) {
+                        falls.extend(rapids.take().and_then(|top| rapids_to(top, i)));
+                        falls.push(fall(FallKind::Waterfall, i, bottom));
+                        i = bottom;
+                        continue;
+                    }
+
+                    // Steep terrain has a normal leaning far from the vertical
+                    let (x, y) = cells[i];
+                    if elevation.get_normal(x, y).z.abs() < steep {
+                        rapids.get_or_insert(i);
+                    } else {
+                        falls.extend(rapids.take().and_then(|top| rapids_to(top, i)));
+                    }
+
+                    i += 1;
+                }
+                falls.extend(rapids.and_then(|top| rapids_to(top, cells.len() - 1)));
+            }
+        }
+
+        falls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::drainage::Drainage;
+    use crate::map::grid::Grid;
+
+    #[test]
+    fn waterfalls_are_found_at_cliffs() {
+        // A gentle slope up from the sea to the west, broken by a cliff halfway across
+        let mut elevation = Elevation::from(Grid::from_fn(60, |x, y| {
+            if x < 5 || y == 0 || x == 59 || y == 59 {
+                -0.1
+            } else {
+                let cliff = if x >= 30 { 0.05 } else { 0.0 };
+                0.001 * f64::from(x) + 0.0005 * f64::from(y.abs_diff(30)) + cliff
+            }
+        }));
+        elevation.update_coast();
+        let drainage = Drainage::new(&elevation);
+        let (watersheds, _) = Watershed::create_all(&drainage, &elevation);
+
+        let falls = Falls::default().find(&elevation, &watersheds);
+        let waterfalls: Vec<_> = falls
+            .iter()
+            .filter(|fall| fall.kind == FallKind::Waterfall)
+            .collect();
+        assert!(!waterfalls.is_empty());
+        for fall in waterfalls {
+            assert!((28..=31).contains(&fall.x), "waterfall at {}", fall.x);
+            assert!(fall.height >= 0.04);
+        }
+    }
+}
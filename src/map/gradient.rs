@@ -1,3 +1,4 @@
+use super::shape::IslandShape;
 use rand::prelude::*;
 use rand_xoshiro::Xoshiro256StarStar;
 use std::f64::consts::{E, PI, TAU};
@@ -122,3 +123,9 @@ impl Gradient {
         }) / self.layers.len() as f64
     }
 }
+
+impl IslandShape for Gradient {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        Gradient::at(self, x, y)
+    }
+}
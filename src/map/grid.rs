@@ -0,0 +1,364 @@
+//! A square grid of per-cell data
+//!
+//! Every per-cell layer of the map (elevation, water, sediment, and so on) is stored in a `Grid`,
+//! which takes care of index conversion, bounds checking, neighborhoods, and sampling between
+//! cells.
+
+use std::ops::{Index, IndexMut};
+
+/// Offsets to the 4-connected (von Neumann) neighbors of a cell
+#[allow(dead_code)]
+const NEIGHBORS_4: [(i64, i64); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+/// Offsets to the 8-connected (Moore) neighbors of a cell
+const NEIGHBORS_8: [(i64, i64); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid<T> {
+    cells: Vec<T>,
+    size: u32,
+}
+
+impl<T: Clone> Grid<T> {
+    /// Create a new grid with every cell set to `value`
+    pub fn new(size: u32, value: T) -> Self {
+        Self {
+            cells: vec![value; (size * size) as usize],
+            size,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<T> Grid<T> {
+    /// Create a new grid by calling `f` with the (x, y) coordinates of every cell
+    pub fn from_fn<F>(size: u32, mut f: F) -> Self
+    where
+        F: FnMut(u32, u32) -> T,
+    {
+        let mut cells = Vec::with_capacity((size * size) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                cells.push(f(x, y));
+            }
+        }
+
+        Self { cells, size }
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn to_idx(&self, x: u32, y: u32) -> usize {
+        (x + y * self.size) as usize
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    #[inline(always)]
+    pub fn from_idx(&self, idx: usize) -> (u32, u32) {
+        let idx = idx as u32;
+
+        (idx % self.size, idx / self.size)
+    }
+
+    #[inline(always)]
+    pub fn in_bounds(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && x < i64::from(self.size) && y < i64::from(self.size)
+    }
+
+    /// Get the value at (x, y), or `None` if it's outside the grid
+    pub fn get(&self, x: u32, y: u32) -> Option<&T> {
+        if x < self.size && y < self.size {
+            Some(&self.cells[self.to_idx(x, y)])
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the 4-connected neighbors of (x, y) that lie within the grid
+    pub fn neighbors4(&self, x: u32, y: u32) -> impl Iterator<Item = (u32, u32)> {
+        self.offsets(x, y, &NEIGHBORS_4)
+    }
+
+    /// Iterate over the 8-connected neighbors of (x, y) that lie within the grid
+    pub fn neighbors8(&self, x: u32, y: u32) -> impl Iterator<Item = (u32, u32)> {
+        self.offsets(x, y, &NEIGHBORS_8)
+    }
+
+    fn offsets(
+        &self,
+        x: u32,
+        y: u32,
+        offsets: &'static [(i64, i64)],
+    ) -> impl Iterator<Item = (u32, u32)> {
+        let size = i64::from(self.size);
+        let (x, y) = (i64::from(x), i64::from(y));
+
+        offsets.iter().filter_map(move |(dx, dy)| {
+            let (x, y) = (x + dx, y + dy);
+            if x >= 0 && y >= 0 && x < size && y < size {
+                Some((x as u32, y as u32))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.cells.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.cells.iter_mut()
+    }
+
+    /// Iterate over every cell along with its (x, y) coordinates
+    pub fn cells(&self) -> impl Iterator<Item = ((u32, u32), &T)> {
+        let size = self.size;
+
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(idx, value)| ((idx as u32 % size, idx as u32 / size), value))
+    }
+
+    /// Create a new grid by applying `f` to every cell
+    pub fn map<U, F>(&self, f: F) -> Grid<U>
+    where
+        F: FnMut(&T) -> U,
+    {
+        Grid {
+            cells: self.cells.iter().map(f).collect(),
+            size: self.size,
+        }
+    }
+
+    /// Create a new grid by combining each cell with the matching cell of `other`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the two grids are not the same size
+    pub fn zip_with<U, V, F>(&self, other: &Grid<U>, mut f: F) -> Grid<V>
+    where
+        F: FnMut(&T, &U) -> V,
+    {
+        assert_eq!(self.size, other.size, "Grids must be the same size!");
+
+        Grid {
+            cells: self
+                .cells
+                .iter()
+                .zip(other.cells.iter())
+                .map(|(a, b)| f(a, b))
+                .collect(),
+            size: self.size,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Grid<f64> {
+    /// Get the value at (x, y), clamping the coordinates to the edges of the grid
+    #[inline(always)]
+    fn clamped(&self, x: i64, y: i64) -> f64 {
+        let max = i64::from(self.size) - 1;
+
+        self.cells[self.to_idx(x.clamp(0, max) as u32, y.clamp(0, max) as u32)]
+    }
+
+    /// Sample the grid between cells using bilinear interpolation
+    ///
+    /// Cell (x, y) is centered on the point (x, y); positions outside the grid are clamped to its
+    /// edges.
+    pub fn sample_bilinear(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = lerp(self.clamped(x0, y0), self.clamped(x0 + 1, y0), tx);
+        let bottom = lerp(self.clamped(x0, y0 + 1), self.clamped(x0 + 1, y0 + 1), tx);
+
+        lerp(top, bottom, ty)
+    }
+
+    /// Sample the grid between cells using bicubic (Catmull-Rom) interpolation
+    ///
+    /// Smoother than bilinear sampling, at the cost of reading a 4x4 neighborhood; the result may
+    /// overshoot the surrounding values slightly.
+    pub fn sample_bicubic(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let mut rows = [0.0; 4];
+        for (row, dy) in rows.iter_mut().zip(-1..=2) {
+            let y = y0 + dy;
+            *row = cubic(
+                self.clamped(x0 - 1, y),
+                self.clamped(x0, y),
+                self.clamped(x0 + 1, y),
+                self.clamped(x0 + 2, y),
+                tx,
+            );
+        }
+
+        cubic(rows[0], rows[1], rows[2], rows[3], ty)
+    }
+
+    /// Add `amount` to the four cells surrounding (x, y), weighted by their bilinear weights
+    ///
+    /// This is the inverse of `sample_bilinear`; any cells that fall outside the grid are skipped.
+    pub fn add_bilinear(&mut self, x: f64, y: f64, amount: f64) {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let corners = [
+            (x0, y0, (1.0 - tx) * (1.0 - ty)),
+            (x0 + 1, y0, tx * (1.0 - ty)),
+            (x0, y0 + 1, (1.0 - tx) * ty),
+            (x0 + 1, y0 + 1, tx * ty),
+        ];
+        for &(x, y, weight) in corners.iter() {
+            if self.in_bounds(x, y) {
+                let idx = self.to_idx(x as u32, y as u32);
+                self.cells[idx] += amount * weight;
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[inline(always)]
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Catmull-Rom cubic interpolation between `b` and `c`
+#[inline(always)]
+pub(super) fn cubic(a: f64, b: f64, c: f64, d: f64, t: f64) -> f64 {
+    b + 0.5 * t * (c - a + t * (2.0 * a - 5.0 * b + 4.0 * c - d + t * (3.0 * (b - c) + d - a)))
+}
+
+impl<T> Index<usize> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &Self::Output {
+        &self.cells[idx]
+    }
+}
+
+impl<T> Index<(u32, u32)> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, key: (u32, u32)) -> &Self::Output {
+        assert!(
+            key.0 < self.size,
+            "X coordinate is out of bounds! {:?}",
+            key
+        );
+        assert!(
+            key.1 < self.size,
+            "Y coordinate is out of bounds! {:?}",
+            key
+        );
+
+        &self.cells[self.to_idx(key.0, key.1)]
+    }
+}
+
+impl<T> IndexMut<usize> for Grid<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        &mut self.cells[idx]
+    }
+}
+
+impl<T> IndexMut<(u32, u32)> for Grid<T> {
+    fn index_mut(&mut self, key: (u32, u32)) -> &mut Self::Output {
+        assert!(
+            key.0 < self.size,
+            "X coordinate is out of bounds! {:?}",
+            key
+        );
+        assert!(
+            key.1 < self.size,
+            "Y coordinate is out of bounds! {:?}",
+            key
+        );
+
+        let idx = self.to_idx(key.0, key.1);
+
+        &mut self.cells[idx]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbors_stay_in_bounds() {
+        let grid = Grid::new(4, 0.0);
+
+        assert_eq!(grid.neighbors4(0, 0).count(), 2);
+        assert_eq!(grid.neighbors8(0, 0).count(), 3);
+        assert_eq!(grid.neighbors4(1, 1).count(), 4);
+        assert_eq!(grid.neighbors8(1, 1).count(), 8);
+        assert_eq!(grid.neighbors8(3, 2).count(), 5);
+    }
+
+    #[test]
+    fn sampling_matches_cells() {
+        let grid = Grid::from_fn(8, |x, y| f64::from(x * y));
+
+        for ((x, y), &value) in grid.cells() {
+            let (fx, fy) = (f64::from(x), f64::from(y));
+            assert!((grid.sample_bilinear(fx, fy) - value).abs() < 1e-9);
+            assert!((grid.sample_bicubic(fx, fy) - value).abs() < 1e-9);
+        }
+
+        // Halfway between cells bilinear sampling is the mean of the corners
+        let mean = (grid[(2, 2)] + grid[(3, 2)] + grid[(2, 3)] + grid[(3, 3)]) / 4.0;
+        assert!((grid.sample_bilinear(2.5, 2.5) - mean).abs() < 1e-9);
+    }
+
+    #[test]
+    fn add_bilinear_conserves_amount() {
+        let mut grid = Grid::new(8, 0.0);
+        grid.add_bilinear(3.25, 4.6, 2.0);
+
+        assert!((grid.iter().sum::<f64>() - 2.0).abs() < 1e-9);
+        assert!(grid[(3, 5)] > grid[(4, 4)]);
+    }
+
+    #[test]
+    fn map_and_zip() {
+        let a = Grid::from_fn(5, |x, _| f64::from(x));
+        let b = a.map(|v| v * 2.0);
+        let c = a.zip_with(&b, |a, b| b - a);
+
+        assert_eq!(a, c);
+    }
+}
@@ -0,0 +1,54 @@
+//! Small numeric helpers shared across our map modules
+
+/// Smooth Hermite interpolation from 0.0 to 1.0 as `t` goes from 0.0 to 1.0, clamped beyond
+#[inline(always)]
+pub fn smoothstep(t: f64) -> f64 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Rise smoothly from 0.0 where `x` is at `low` to 1.0 where it's at `high`
+///
+/// Where `low` and `high` are the same, this is a hard step up at `low`.
+#[inline(always)]
+pub fn ramp(low: f64, high: f64, x: f64) -> f64 {
+    if high > low {
+        smoothstep((x - low) / (high - low))
+    } else if x >= low {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Moisture for each land point from its distance to fresh water, with water left at 0.0
+///
+/// Moisture falls off with distance, but we redistribute it so that every level of moisture is
+/// equally common over the land; otherwise our biomes would depend on the number of rivers.
+pub fn rank_moisture<D: Ord, F: Fn(usize) -> bool>(distances: &[D], is_land: F) -> Vec<f64> {
+    let mut land: Vec<_> = (0..distances.len()).filter(|&p| is_land(p)).collect();
+    land.sort_by_key(|&p| std::cmp::Reverse(&distances[p]));
+
+    let mut moisture = vec![0.0; distances.len()];
+    let scale = (land.len().max(2) - 1) as f64;
+    for (rank, &p) in land.iter().enumerate() {
+        moisture[p] = rank as f64 / scale;
+    }
+
+    moisture
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_step_when_their_ends_meet() {
+        assert_eq!(ramp(0.0, 2.0, 1.0), 0.5);
+        assert_eq!(ramp(0.0, 2.0, 3.0), 1.0);
+
+        assert_eq!(ramp(1.0, 1.0, 0.5), 0.0);
+        assert_eq!(ramp(1.0, 1.0, 1.0), 1.0);
+        assert_eq!(ramp(1.0, 1.0, 1.5), 1.0);
+    }
+}
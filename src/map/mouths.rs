@@ -0,0 +1,434 @@
+//! Shape the mouths of our rivers
+//!
+//! Where a river brings plenty of sediment into calm water, it builds a delta: a fan of new land
+//! spreading out to sea, crossed by distributary channels. Where the sea has instead flooded the
+//! low-lying end of a river's valley, the valley is drowned into a funnel-shaped estuary. Every
+//! other river simply runs into the sea.
+
+use super::coast::Coastal;
+use super::drainage::Drainage;
+use super::elevation::Elevation;
+use super::grid::Grid;
+use super::strahler::Strahler;
+use super::watershed::Watershed;
+use super::SEA_LEVEL;
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+use std::f64::consts::{PI, TAU};
+
+/// Distance up the main stem we look to find which way a river is flowing as it meets the sea
+const APPROACH: usize = 5;
+
+/// What happens where a river meets the sea
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouthKind {
+    /// A fan of deposited sediment crossed by distributary channels
+    Delta,
+    /// A drowned river valley
+    Estuary,
+    /// A river running straight into the sea
+    Plain,
+}
+
+/// A river's mouth, and what has formed there
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct RiverMouth {
+    pub x: u32,
+    pub y: u32,
+    pub kind: MouthKind,
+    /// Strahler order of the river at its mouth
+    pub order: Strahler,
+    /// Number of cells draining through the mouth
+    pub flow: f64,
+    /// Sediment the river has carried into the sea around its mouth
+    pub sediment: f64,
+    /// How exposed the mouth is to waves, in the range [0.0, 1.0]
+    pub exposure: f64,
+    /// Courses of a delta's distributary channels, each running out to sea from the mouth
+    pub distributaries: Vec<Vec<(u32, u32)>>,
+}
+
+/// Parameters for shaping river mouths
+#[derive(Debug, Clone)]
+pub struct Mouths {
+    /// Lowest Strahler order of river that forms a delta or estuary
+    pub min_order: u32,
+    /// Distance, in cells, from a mouth within which we gather the sediment reaching the sea
+    pub catchment: f64,
+    /// Sediment per cell of flow above which a river can build a delta
+    pub delta_supply: f64,
+    /// Exposure to waves above which a delta is washed away as fast as it's built
+    pub delta_exposure: f64,
+    /// Height above sea level to which deltas are built at their apex
+    pub delta_height: f64,
+    /// Height of delta built from each unit of sediment
+    pub delta_volume: f64,
+    /// Widest angle, in radians, across which a delta fans out
+    pub delta_spread: f64,
+    /// Furthest distance, in cells, a delta reaches from its river's mouth
+    pub max_delta_radius: f64,
+    /// Most distributary channels crossing a delta
+    pub distributaries: u32,
+    /// Depth below sea level of distributary channels
+    pub channel_depth: f64,
+    /// Fraction of the sea around a mouth below which the mouth is in an inlet
+    pub inlet: f64,
+    /// Height above sea level below which a valley floor is low enough to be drowned
+    pub estuary_relief: f64,
+    /// Distance, in cells, up the main stem that an estuary reaches
+    pub estuary_length: f64,
+    /// Distance, in cells, from the river to the banks of an estuary at its mouth
+    pub estuary_width: f64,
+    /// Depth below sea level of an estuary at its mouth
+    pub estuary_depth: f64,
+    /// How waves reach the coast, for measuring each mouth's exposure
+    pub waves: Coastal,
+}
+
+impl Default for Mouths {
+    fn default() -> Self {
+        Self {
+            min_order: 2,
+            catchment: 6.0,
+            delta_supply: 0.003,
+            delta_exposure: 0.5,
+            delta_height: 0.003,
+            delta_volume: 0.3,
+            delta_spread: PI * 0.75,
+            max_delta_radius: 30.0,
+            distributaries: 5,
+            channel_depth: 0.002,
+            inlet: 0.3,
+            estuary_relief: 0.02,
+            estuary_length: 30.0,
+            estuary_width: 5.0,
+            estuary_depth: 0.01,
+            waves: Default::default(),
+        }
+    }
+}
+
+impl Mouths {
+    /// Shape the mouth of each river in `watersheds`, returning what formed at each
+    ///
+    /// Sediment gathered into a mouth is taken from `sediment`.
+    pub fn form(
+        &self,
+        elevation: &mut Elevation,
+        watersheds: &[Watershed],
+        drainage: &Drainage,
+        sediment: &mut Grid<f64>,
+        rng: &mut Xoshiro256StarStar,
+    ) -> Vec<RiverMouth> {
+        let mut mouths = Vec::new();
+
+        for river in watersheds.iter().filter_map(|watershed| watershed.river()) {
+            let mouth = river.mouth();
+            let (x, y) = elevation.from_idx(mouth.idx);
+            let stem: Vec<_> = river.main_stem(0).map(|id| river.node(id).idx).collect();
+            let flow = stem.get(1).map_or(1.0, |&idx| drainage.area()[idx]);
+
+            let mut found = RiverMouth {
+                x,
+                y,
+                kind: MouthKind::Plain,
+                order: mouth.strahler,
+                flow,
+                sediment: 0.0,
+                exposure: self.waves.exposure(elevation, x, y),
+                distributaries: Vec::new(),
+            };
+            if u32::from(mouth.strahler) < self.min_order || stem.len() < 2 {
+                mouths.push(found);
+                continue;
+            }
+
+            // Gather up the sediment reaching the sea around our mouth
+            let reach = self.catchment.ceil() as i64;
+            for (cx, cy) in around(elevation.size(), x, y, reach) {
+                if distance(x, y, cx, cy) <= self.catchment {
+                    found.sediment += std::mem::take(&mut sediment[(cx, cy)]);
+                }
+            }
+
+            // The sea floods the low end of a valley, or fills an inlet the river runs into
+            let low = stem
+                .iter()
+                .take(self.estuary_length as usize)
+                .filter(|&&idx| elevation[idx] < SEA_LEVEL + self.estuary_relief)
+                .count();
+            let drowned = low * 2 >= stem.len().min(self.estuary_length as usize)
+                || self.sea_around(elevation, x, y) < self.inlet;
+
+            if found.sediment / flow >= self.delta_supply && found.exposure <= self.delta_exposure {
+                found.kind = MouthKind::Delta;
+                // Rivers leave the land in the direction they were flowing as they reached it
+                let (ax, ay) = elevation.from_idx(stem[APPROACH.min(stem.len() - 1)]);
+                let heading = (f64::from(y) - f64::from(ay)).atan2(f64::from(x) - f64::from(ax));
+                found.distributaries =
+                    self.build_delta(elevation, x, y, heading, found.sediment, rng);
+            } else if drowned {
+                found.kind = MouthKind::Estuary;
+                self.drown(elevation, &stem);
+            }
+
+            mouths.push(found);
+        }
+
+        mouths
+    }
+
+    /// Fraction of the cells around (x, y) that are sea
+    fn sea_around(&self, elevation: &Elevation, x: u32, y: u32) -> f64 {
+        let cells: Vec<_> = around(elevation.size(), x, y, self.catchment.ceil() as i64).collect();
+        let sea = cells
+            .iter()
+            .filter(|&&cell| elevation[cell] <= SEA_LEVEL)
+            .count();
+
+        sea as f64 / cells.len() as f64
+    }
+
+    /// Build a delta out from (x, y) towards `heading`, returning its distributary channels
+    fn build_delta(
+        &self,
+        elevation: &mut Elevation,
+        x: u32,
+        y: u32,
+        heading: f64,
+        sediment: f64,
+        rng: &mut Xoshiro256StarStar,
+    ) -> Vec<Vec<(u32, u32)>> {
+        // Our delta grows in a few lobes, so its front isn't a perfect arc
+        let lobes = rng.gen_range(2.0..4.0);
+        let phase = rng.gen_range(0.0..TAU);
+
+        // Fill the shallowest cells nearest the mouth first, until our sediment runs out
+        let reach = self.max_delta_radius.ceil() as i64;
+        let mut cells: Vec<_> = around(elevation.size(), x, y, reach)
+            .filter_map(|(cx, cy)| {
+                let angle = (f64::from(cy) - f64::from(y)).atan2(f64::from(cx) - f64::from(x));
+                let off = angle_between(angle, heading);
+                if off > self.delta_spread / 2.0 {
+                    return None;
+                }
+
+                let dist = distance(x, y, cx, cy) / self.max_delta_radius;
+                let lobed = dist / (1.0 + 0.25 * (lobes * off + phase).sin());
+                Some(((cx, cy), dist, lobed))
+            })
+            .filter(|&(_, dist, _)| dist <= 1.0)
+            .collect();
+        cells.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+        let mut budget = sediment * self.delta_volume;
+        let mut built = Grid::new(elevation.size(), false);
+        let mut radius: f64 = 0.0;
+        for (cell, dist, _) in cells {
+            // Our delta slopes gently down from its apex to its front
+            let target = SEA_LEVEL + self.delta_height * (1.0 - dist);
+            let needed = (target - elevation[cell]).max(0.0);
+            if needed > budget {
+                elevation[cell] += budget;
+                break;
+            }
+
+            elevation[cell] += needed;
+            budget -= needed;
+            built[cell] = true;
+            radius = radius.max(dist * self.max_delta_radius);
+        }
+
+        // Now cut distributaries across it, fanning out from the mouth
+        let count = ((radius / 8.0).ceil() as u32).clamp(1, self.distributaries.max(1));
+        let spread = self.delta_spread * 0.6;
+        (0..count)
+            .map(|i| {
+                let mut angle = if count == 1 {
+                    heading
+                } else {
+                    heading - spread / 2.0 + spread * f64::from(i) / f64::from(count - 1)
+                };
+                let (mut px, mut py) = (f64::from(x), f64::from(y));
+                let mut channel = vec![(x, y)];
+
+                // Wander out to sea, cutting our channel until we've crossed the delta
+                loop {
+                    angle += rng.gen_range(-0.3..0.3);
+                    angle = heading + (angle - heading).clamp(-spread, spread);
+                    px += angle.cos() * 0.5;
+                    py += angle.sin() * 0.5;
+
+                    let (cx, cy) = (px.round() as i64, py.round() as i64);
+                    if !elevation.in_bounds(cx, cy) {
+                        break;
+                    }
+                    let cell = (cx as u32, cy as u32);
+                    if !built[cell] {
+                        break;
+                    }
+                    if channel.last() != Some(&cell) {
+                        elevation[cell] = elevation[cell].min(SEA_LEVEL - self.channel_depth);
+                        channel.push(cell);
+                    }
+                }
+
+                channel
+            })
+            .collect()
+    }
+
+    /// Drown the low end of the valley along `stem`, which runs upstream from the mouth
+    fn drown(&self, elevation: &mut Elevation, stem: &[usize]) {
+        let mut travelled = 0.0;
+        for pair in stem.windows(2) {
+            let (x, y) = elevation.from_idx(pair[1]);
+            let (px, py) = elevation.from_idx(pair[0]);
+            travelled += distance(x, y, px, py);
+            if travelled >= self.estuary_length {
+                break;
+            }
+
+            // Our estuary narrows and shallows into the river upstream
+            let fraction = 1.0 - travelled / self.estuary_length;
+            let width = 1.0 + self.estuary_width * fraction;
+            let depth = self.estuary_depth * fraction;
+            for (cx, cy) in around(elevation.size(), x, y, width.ceil() as i64) {
+                let dist = distance(x, y, cx, cy);
+                let height = &mut elevation[(cx, cy)];
+                if dist > width || *height >= SEA_LEVEL + self.estuary_relief {
+                    continue;
+                }
+
+                *height = height.min(SEA_LEVEL - depth * (1.0 - dist / width));
+            }
+        }
+    }
+}
+
+/// Every cell on a map of the given size within `reach` cells of (x, y) on each axis
+fn around(size: u32, x: u32, y: u32, reach: i64) -> impl Iterator<Item = (u32, u32)> {
+    let (x, y, size) = (i64::from(x), i64::from(y), i64::from(size));
+    (-reach..=reach)
+        .flat_map(move |dy| (-reach..=reach).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&(cx, cy)| (0..size).contains(&cx) && (0..size).contains(&cy))
+        .map(|(cx, cy)| (cx as u32, cy as u32))
+}
+
+fn distance(x1: u32, y1: u32, x2: u32, y2: u32) -> f64 {
+    (f64::from(x1) - f64::from(x2)).hypot(f64::from(y1) - f64::from(y2))
+}
+
+/// The smallest angle between two directions, in radians
+fn angle_between(a: f64, b: f64) -> f64 {
+    let diff = (a - b).rem_euclid(TAU);
+    diff.min(TAU - diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A valley running east down to the sea, its floor rising `slope` for each cell inland
+    fn valley(slope: f64) -> Elevation {
+        let mut elevation = Elevation::from(Grid::from_fn(60, |x, y| {
+            if x == 0 || y == 0 || y == 59 || x >= 45 {
+                -0.01
+            } else {
+                slope * f64::from(45 - x) + 0.002 * f64::from(y.abs_diff(30))
+            }
+        }));
+        elevation.update_coast();
+
+        elevation
+    }
+
+    /// Form the mouths of the rivers on `elevation`, returning the mouth of the largest river
+    fn form(mouths: &Mouths, elevation: &mut Elevation, sediment: &mut Grid<f64>) -> RiverMouth {
+        let drainage = Drainage::new(elevation);
+        let (watersheds, _) = Watershed::create_all(&drainage, elevation);
+        let mut rng = Xoshiro256StarStar::seed_from_u64(0);
+
+        mouths
+            .form(elevation, &watersheds, &drainage, sediment, &mut rng)
+            .into_iter()
+            .max_by(|a, b| a.flow.total_cmp(&b.flow))
+            .unwrap()
+    }
+
+    #[test]
+    fn mouths_are_classified() {
+        let mouths = Mouths::default();
+
+        // A low-lying valley is drowned by the sea
+        let mut elevation = valley(0.0003);
+        let mut sediment = Grid::new(60, 0.0);
+        let mouth = form(&mouths, &mut elevation, &mut sediment);
+        assert_eq!(mouth.kind, MouthKind::Estuary);
+        assert!(elevation[(40, 30)] < SEA_LEVEL);
+
+        // A steep valley simply runs into the sea
+        let mut elevation = valley(0.003);
+        let mouth = form(&mouths, &mut elevation, &mut sediment);
+        assert_eq!(mouth.kind, MouthKind::Plain);
+        assert!(elevation[(40, 30)] > SEA_LEVEL);
+
+        // A river bringing plenty of sediment into calm water builds a delta
+        let calm = Mouths {
+            waves: Coastal {
+                wave_direction: Some(PI),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut elevation = valley(0.003);
+        let mut sediment = Grid::from_fn(60, |x, y| {
+            if (45..=47).contains(&x) && y.abs_diff(30) <= 1 {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        let mouth = form(&calm, &mut elevation, &mut sediment);
+        assert_eq!(mouth.kind, MouthKind::Delta);
+        let built = elevation
+            .cells()
+            .filter(|&((x, _), &height)| x >= 45 && height > SEA_LEVEL)
+            .count();
+        assert!(built > 0);
+    }
+
+    #[test]
+    fn deltas_build_out_to_sea() {
+        // A straight coast with a shallow shelf running out to the east
+        let mut elevation = Elevation::from(Grid::from_fn(
+            100,
+            |x, _| {
+                if x < 50 {
+                    0.01
+                } else {
+                    -0.002
+                }
+            },
+        ));
+        let mut rng = Xoshiro256StarStar::seed_from_u64(0);
+
+        let mouths = Mouths::default();
+        let channels = mouths.build_delta(&mut elevation, 50, 50, 0.0, 1.0, &mut rng);
+
+        // New land has been built out to sea from the mouth...
+        let built = (51..100)
+            .filter(|&x| elevation[(x, 50)] > SEA_LEVEL)
+            .count();
+        assert!(built > 0);
+        assert!(elevation[(40, 50)] == 0.01);
+
+        // ...and is crossed by channels running out to sea
+        assert!(!channels.is_empty());
+        for channel in channels.iter() {
+            assert_eq!(channel[0], (50, 50));
+            assert!(channel[1..].iter().all(|&cell| elevation[cell] < SEA_LEVEL));
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! Configurable noise for terrain generation
+//!
+//! Terrain noise is described by a tree of `Noise` nodes: sources such as simplex, Perlin,
+//! cellular, ridged, or billow noise, which can be combined, rescaled, terraced, and used to warp
+//! each other's domains. Because the tree can be loaded from a RON file, new kinds of terrain
+//! need no code changes; see the `presets` directory for examples.
+
+use super::math::ramp;
+use bracket_noise::prelude::*;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Parameters shared by our fractal noise sources
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Fractal {
+    pub frequency: f64,
+    pub octaves: u32,
+    /// Amplitude of each octave relative to the last
+    pub gain: f64,
+    /// Frequency of each octave relative to the last
+    pub lacunarity: f64,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self {
+            frequency: 2.0,
+            octaves: 5,
+            gain: 0.5,
+            lacunarity: 2.0,
+        }
+    }
+}
+
+/// A node in our noise tree
+#[derive(Debug, Clone, Deserialize)]
+pub enum Noise {
+    /// Fractal simplex noise; smooth rolling terrain
+    Simplex(Fractal),
+    /// Fractal Perlin noise; similar to simplex, but more grid-aligned
+    Perlin(Fractal),
+    /// Ridged multifractal noise; sharp ridgelines, good for mountains
+    Ridged(Fractal),
+    /// Billow noise; rounded hills separated by creases
+    Billow(Fractal),
+    /// Cellular (Worley) noise: the distance to the nearest of a set of random points
+    Cellular { frequency: f64 },
+    /// The same value everywhere
+    Constant(f64),
+    /// Sum of all our inputs
+    Add(Vec<Noise>),
+    /// Product of all our inputs
+    Multiply(Vec<Noise>),
+    /// Largest of all our inputs
+    Max(Vec<Noise>),
+    /// Smallest of all our inputs
+    Min(Vec<Noise>),
+    /// Rescale `source` to `source * scale + bias`
+    Scale {
+        source: Box<Noise>,
+        scale: f64,
+        bias: f64,
+    },
+    /// Flatten `source` into `steps` terraces per unit, separated by steep risers, for mesas
+    Terrace { source: Box<Noise>, steps: f64 },
+    /// Distort the coordinates at which `source` is sampled by the value of `warp`
+    Warp {
+        source: Box<Noise>,
+        warp: Box<Noise>,
+        strength: f64,
+    },
+}
+
+impl Default for Noise {
+    /// Simplex FBM "pulled up" to lie (mostly) within [0.0, 0.5]
+    fn default() -> Self {
+        Self::Scale {
+            source: Box::new(Self::Simplex(Fractal {
+                frequency: 2.0,
+                octaves: 5,
+                gain: 0.6,
+                lacunarity: 2.0,
+            })),
+            scale: 0.5,
+            bias: 0.25,
+        }
+    }
+}
+
+/// A combinator in a noise tree with no inputs to combine
+///
+/// An empty `Max` or `Min` would otherwise give infinite heights everywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmptyCombinator(&'static str);
+
+impl fmt::Display for EmptyCombinator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in noise tree has no inputs", self.0)
+    }
+}
+
+impl Error for EmptyCombinator {}
+
+/// Why a noise tree couldn't be read
+#[derive(Debug)]
+pub enum NoiseError {
+    /// The RON couldn't be parsed
+    Parse(ron::error::SpannedError),
+    /// The tree parsed, but has a combinator with nothing to combine
+    Empty(EmptyCombinator),
+}
+
+impl fmt::Display for NoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => err.fmt(f),
+            Self::Empty(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for NoiseError {}
+
+impl From<ron::error::SpannedError> for NoiseError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<EmptyCombinator> for NoiseError {
+    fn from(err: EmptyCombinator) -> Self {
+        Self::Empty(err)
+    }
+}
+
+#[allow(dead_code)]
+impl Noise {
+    /// Parse a noise tree from RON, checking that it can be built
+    pub fn from_ron(ron: &str) -> Result<Self, NoiseError> {
+        let noise: Self = ron::from_str(ron)?;
+        noise.validate()?;
+
+        Ok(noise)
+    }
+
+    /// Load a noise tree from a RON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_ron(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Build the noise generators for this tree
+    ///
+    /// Each source in the tree gets its own seed, counting up from `seed`.
+    pub fn build(&self, seed: u64) -> Result<NoiseGraph, EmptyCombinator> {
+        self.validate()?;
+
+        let mut next_seed = seed;
+        Ok(NoiseGraph(self.build_node(&mut next_seed)))
+    }
+
+    /// Check that every combinator in this tree has inputs to combine
+    pub fn validate(&self) -> Result<(), EmptyCombinator> {
+        let (name, nodes) = match self {
+            Self::Add(nodes) => ("Add", nodes),
+            Self::Multiply(nodes) => ("Multiply", nodes),
+            Self::Max(nodes) => ("Max", nodes),
+            Self::Min(nodes) => ("Min", nodes),
+            Self::Scale { source, .. } | Self::Terrace { source, .. } => return source.validate(),
+            Self::Warp { source, warp, .. } => {
+                source.validate()?;
+                return warp.validate();
+            }
+            _ => return Ok(()),
+        };
+
+        if nodes.is_empty() {
+            return Err(EmptyCombinator(name));
+        }
+        nodes.iter().try_for_each(Self::validate)
+    }
+
+    fn build_node(&self, seed: &mut u64) -> Node {
+        match self {
+            Self::Simplex(fractal) => {
+                fractal_source(seed, NoiseType::SimplexFractal, FractalType::FBM, fractal)
+            }
+            Self::Perlin(fractal) => {
+                fractal_source(seed, NoiseType::PerlinFractal, FractalType::FBM, fractal)
+            }
+            Self::Ridged(fractal) => fractal_source(
+                seed,
+                NoiseType::SimplexFractal,
+                FractalType::RigidMulti,
+                fractal,
+            ),
+            Self::Billow(fractal) => fractal_source(
+                seed,
+                NoiseType::SimplexFractal,
+                FractalType::Billow,
+                fractal,
+            ),
+            Self::Cellular { frequency } => {
+                let mut noise = next_source(seed);
+                noise.set_noise_type(NoiseType::Cellular);
+                noise.set_cellular_return_type(CellularReturnType::Distance);
+                noise.set_frequency(*frequency as f32);
+
+                Node::Source(noise)
+            }
+            Self::Constant(value) => Node::Constant(*value),
+            Self::Add(nodes) => Node::Add(build_all(nodes, seed)),
+            Self::Multiply(nodes) => Node::Multiply(build_all(nodes, seed)),
+            Self::Max(nodes) => Node::Max(build_all(nodes, seed)),
+            Self::Min(nodes) => Node::Min(build_all(nodes, seed)),
+            Self::Scale {
+                source,
+                scale,
+                bias,
+            } => Node::Scale(Box::new(source.build_node(seed)), *scale, *bias),
+            Self::Terrace { source, steps } => {
+                Node::Terrace(Box::new(source.build_node(seed)), steps.max(1.0))
+            }
+            Self::Warp {
+                source,
+                warp,
+                strength,
+            } => Node::Warp(
+                Box::new(source.build_node(seed)),
+                Box::new(warp.build_node(seed)),
+                *strength,
+            ),
+        }
+    }
+}
+
+/// Create a new noise source, taking the next seed
+fn next_source(seed: &mut u64) -> FastNoise {
+    let noise = FastNoise::seeded(*seed);
+    *seed = seed.wrapping_add(1);
+
+    noise
+}
+
+fn fractal_source(
+    seed: &mut u64,
+    noise_type: NoiseType,
+    fractal_type: FractalType,
+    fractal: &Fractal,
+) -> Node {
+    let mut noise = next_source(seed);
+    noise.set_noise_type(noise_type);
+    noise.set_fractal_type(fractal_type);
+    noise.set_fractal_octaves(fractal.octaves as i32);
+    noise.set_fractal_gain(fractal.gain as f32);
+    noise.set_fractal_lacunarity(fractal.lacunarity as f32);
+    noise.set_frequency(fractal.frequency as f32);
+
+    Node::Source(noise)
+}
+
+fn build_all(nodes: &[Noise], seed: &mut u64) -> Vec<Node> {
+    nodes.iter().map(|n| n.build_node(seed)).collect()
+}
+
+/// A noise tree ready to be sampled
+pub struct NoiseGraph(Node);
+
+impl NoiseGraph {
+    /// Get the noise value at (x, y)
+    pub fn get(&self, x: f64, y: f64) -> f64 {
+        self.0.get(x, y)
+    }
+}
+
+enum Node {
+    Source(FastNoise),
+    Constant(f64),
+    Add(Vec<Node>),
+    Multiply(Vec<Node>),
+    Max(Vec<Node>),
+    Min(Vec<Node>),
+    Scale(Box<Node>, f64, f64),
+    Terrace(Box<Node>, f64),
+    Warp(Box<Node>, Box<Node>, f64),
+}
+
+impl Node {
+    fn get(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Self::Source(noise) => f64::from(noise.get_noise(x as f32, y as f32)),
+            Self::Constant(value) => *value,
+            Self::Add(nodes) => nodes.iter().map(|n| n.get(x, y)).sum(),
+            Self::Multiply(nodes) => nodes.iter().map(|n| n.get(x, y)).product(),
+            Self::Max(nodes) => nodes
+                .iter()
+                .map(|n| n.get(x, y))
+                .fold(f64::NEG_INFINITY, f64::max),
+            Self::Min(nodes) => nodes
+                .iter()
+                .map(|n| n.get(x, y))
+                .fold(f64::INFINITY, f64::min),
+            Self::Scale(source, scale, bias) => source.get(x, y) * scale + bias,
+            Self::Terrace(source, steps) => {
+                let value = source.get(x, y) * steps;
+                let step = value.floor();
+                // Stay flat for most of each step, then climb steeply to the next
+                (step + ramp(0.8, 1.0, value - step)) / steps
+            }
+            Self::Warp(source, warp, strength) => {
+                // Offset our second sample so the two axes aren't warped identically
+                let dx = warp.get(x, y) * strength;
+                let dy = warp.get(x + 5.2, y + 1.3) * strength;
+                source.get(x + dx, y + dy)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_parse() {
+        for preset in [
+            include_str!("../../presets/ridged.ron"),
+            include_str!("../../presets/mesas.ron"),
+        ]
+        .iter()
+        {
+            let noise = Noise::from_ron(preset).unwrap();
+            let graph = noise.build(1).unwrap();
+            assert!(graph.get(0.3, 0.7).is_finite());
+        }
+    }
+
+    #[test]
+    fn combinators() {
+        let noise = Noise::from_ron(
+            "Add([Constant(1.0), Multiply([Constant(2.0), Constant(3.0)]), Max([Constant(-1.0), \
+             Constant(0.5)])])",
+        )
+        .unwrap();
+
+        assert_eq!(noise.build(0).unwrap().get(0.0, 0.0), 7.5);
+    }
+
+    #[test]
+    fn empty_combinators_are_rejected() {
+        for ron in ["Max([])", "Min([])", "Add([])", "Multiply([])"].iter() {
+            assert!(
+                matches!(Noise::from_ron(ron), Err(NoiseError::Empty(_))),
+                "{}",
+                ron
+            );
+        }
+
+        // However deep in the tree they are, and however the tree was made
+        let nested = Noise::Scale {
+            source: Box::new(Noise::Add(vec![Noise::Constant(1.0), Noise::Min(vec![])])),
+            scale: 1.0,
+            bias: 0.0,
+        };
+        assert_eq!(nested.build(0).err(), Some(EmptyCombinator("Min")));
+    }
+}
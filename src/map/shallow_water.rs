@@ -0,0 +1,316 @@
+//! Simulate hydraulic erosion with a grid-based shallow water model
+//!
+//! Unlike the droplets in `erosion`, water here is tracked in every cell of the map, flowing
+//! between neighbors through virtual "pipes". Water therefore pools into lakes and gathers into
+//! rivers that persist between steps, and the same flow both carries and deposits sediment.
+//!
+//! This is based on the method described in Mei, Decaudin, and Hu's "Fast Hydraulic Erosion
+//! Simulation and Visualization on GPU" (2007).
+
+use super::elevation::{Elevation, HEIGHT_SCALE};
+use super::grid::Grid;
+use super::SEA_LEVEL;
+use nalgebra as na;
+
+type Vec2 = na::Vector2<f64>;
+
+/// Directions of the pipes out of each cell: left, right, up, and down
+const PIPES: [(i64, i64); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+const UP: usize = 2;
+const DOWN: usize = 3;
+
+/// Gravitational acceleration
+const GRAVITY: f64 = 9.81;
+/// Cross-sectional area of the pipes between cells
+const PIPE_AREA: f64 = 1.0;
+/// Length of the pipes between cells, i.e. the width of a cell
+const PIPE_LENGTH: f64 = 1.0;
+/// Water depth below which a cell is considered dry when computing velocities
+const MIN_DEPTH: f64 = 1e-6;
+/// Water depth at which erosion reaches full strength; shallower water carries proportionally
+/// less sediment, so a thin film of rain doesn't scour the whole island
+const EROSION_DEPTH: f64 = 0.01;
+
+/// Parameters for the shallow water simulation
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Time scale ("Delta Time") of each step
+    pub dt: f64,
+    /// Depth of rain falling on each land cell per unit of time
+    pub rain_rate: f64,
+    /// Fraction of water evaporating per unit of time
+    pub evaporation: f64,
+    /// Sediment capacity of flowing water
+    pub capacity: f64,
+    /// Rate at which the terrain is dissolved into sediment
+    pub dissolving: f64,
+    /// Rate at which sediment is deposited onto the terrain
+    pub deposition: f64,
+    /// Smallest slope (as the sine of its angle) used when computing sediment capacity, so that
+    /// water flowing over flat ground can still carry some sediment
+    pub min_tilt: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dt: 0.05,
+            rain_rate: 0.0002,
+            evaporation: 0.02,
+            capacity: 0.01,
+            dissolving: 0.002,
+            deposition: 0.002,
+            min_tilt: 0.05,
+        }
+    }
+}
+
+pub struct ShallowWater {
+    settings: Settings,
+    /// Depth of water in each cell
+    water: Grid<f64>,
+    /// Outflow through each cell's pipes, indexed by `LEFT`, `RIGHT`, `UP`, and `DOWN`
+    flux: Grid<[f64; 4]>,
+    /// Velocity of the water in each cell
+    velocity: Grid<Vec2>,
+    /// Suspended sediment in each cell
+    sediment: Grid<f64>,
+}
+
+#[allow(dead_code)]
+impl ShallowWater {
+    pub fn new(size: u32, settings: Settings) -> Self {
+        Self {
+            settings,
+            water: Grid::new(size, 0.0),
+            flux: Grid::new(size, [0.0; 4]),
+            velocity: Grid::new(size, Vec2::zeros()),
+            sediment: Grid::new(size, 0.0),
+        }
+    }
+
+    /// Run the simulation for the given number of fixed time steps
+    pub fn run(&mut self, elevation: &mut Elevation, steps: u32) {
+        for _ in 0..steps {
+            self.step(elevation);
+        }
+    }
+
+    /// Advance the simulation by a single time step
+    pub fn step(&mut self, elevation: &mut Elevation) {
+        self.rain(elevation);
+        self.update_flux(elevation);
+        self.update_water();
+        self.erode(elevation);
+        self.transport_sediment();
+        self.evaporate();
+    }
+
+    /// Depth of water in each cell
+    pub fn water(&self) -> &Grid<f64> {
+        &self.water
+    }
+
+    /// Velocity of the water in each cell
+    pub fn velocity(&self) -> &Grid<Vec2> {
+        &self.velocity
+    }
+
+    /// Suspended sediment in each cell
+    pub fn sediment(&self) -> &Grid<f64> {
+        &self.sediment
+    }
+
+    /// Find the land cells covered by at least `min_depth` of water, i.e. our rivers and lakes
+    pub fn wet_cells(&self, elevation: &Elevation, min_depth: f64) -> Grid<bool> {
+        elevation.zip_with(&self.water, |&height, &water| {
+            height > SEA_LEVEL && water >= min_depth
+        })
+    }
+
+    /// Add rain to the land, and keep the ocean topped up to sea level
+    fn rain(&mut self, elevation: &Elevation) {
+        let rain = self.settings.dt * self.settings.rain_rate;
+
+        for (water, &height) in self.water.iter_mut().zip(elevation.iter()) {
+            if height <= SEA_LEVEL {
+                // The ocean is an endless reservoir (and sink) of water
+                *water = SEA_LEVEL - height;
+            } else {
+                *water += rain;
+            }
+        }
+    }
+
+    /// Update the outflow through each pipe from the difference in water surface heights
+    fn update_flux(&mut self, elevation: &Elevation) {
+        let dt = self.settings.dt;
+        let scale = dt * PIPE_AREA * GRAVITY / PIPE_LENGTH;
+
+        for idx in 0..self.water.len() {
+            let (x, y) = self.water.from_idx(idx);
+            let surface = elevation[idx] + self.water[idx];
+
+            let mut flux = self.flux[idx];
+            for (pipe, &(dx, dy)) in PIPES.iter().enumerate() {
+                let (nx, ny) = (i64::from(x) + dx, i64::from(y) + dy);
+                flux[pipe] = if self.water.in_bounds(nx, ny) {
+                    let neighbor = self.water.to_idx(nx as u32, ny as u32);
+                    let diff = surface - elevation[neighbor] - self.water[neighbor];
+
+                    (flux[pipe] + scale * diff * HEIGHT_SCALE).max(0.0)
+                } else {
+                    // Nothing flows off the edge of the map
+                    0.0
+                };
+            }
+
+            // Make sure we don't let more water out than this cell holds
+            let total: f64 = flux.iter().sum();
+            if total > 0.0 {
+                let k = (self.water[idx] * PIPE_LENGTH * PIPE_LENGTH / (total * dt)).min(1.0);
+                for f in flux.iter_mut() {
+                    *f *= k;
+                }
+            }
+
+            self.flux[idx] = flux;
+        }
+    }
+
+    /// Move water along the pipes, and find the resulting velocity field
+    fn update_water(&mut self) {
+        let dt = self.settings.dt;
+        let size = i64::from(self.water.size());
+
+        // Outflow of the neighbor in direction `pipe`, or 0 off the edge of the map
+        let flux = &self.flux;
+        let neighbor_flux = |x: u32, y: u32, pipe: usize, dir: usize| -> f64 {
+            let (dx, dy) = PIPES[pipe];
+            let (nx, ny) = (i64::from(x) + dx, i64::from(y) + dy);
+            if nx >= 0 && ny >= 0 && nx < size && ny < size {
+                flux[(nx as u32, ny as u32)][dir]
+            } else {
+                0.0
+            }
+        };
+
+        for idx in 0..self.water.len() {
+            let (x, y) = self.water.from_idx(idx);
+            let out = flux[idx];
+
+            let inflow = neighbor_flux(x, y, LEFT, RIGHT)
+                + neighbor_flux(x, y, RIGHT, LEFT)
+                + neighbor_flux(x, y, UP, DOWN)
+                + neighbor_flux(x, y, DOWN, UP);
+            let outflow: f64 = out.iter().sum();
+
+            let before = self.water[idx];
+            let after = (before + dt * (inflow - outflow) / (PIPE_LENGTH * PIPE_LENGTH)).max(0.0);
+            self.water[idx] = after;
+
+            // Average flow of water through the cell in each axis
+            let flow_x = (neighbor_flux(x, y, LEFT, RIGHT) - out[LEFT] + out[RIGHT]
+                - neighbor_flux(x, y, RIGHT, LEFT))
+                / 2.0;
+            let flow_y = (neighbor_flux(x, y, UP, DOWN) - out[UP] + out[DOWN]
+                - neighbor_flux(x, y, DOWN, UP))
+                / 2.0;
+
+            let depth = (before + after) / 2.0;
+            self.velocity[idx] = if depth > MIN_DEPTH {
+                Vec2::new(flow_x, flow_y) / (PIPE_LENGTH * depth)
+            } else {
+                Vec2::zeros()
+            };
+        }
+    }
+
+    /// Dissolve terrain into fast-moving water, and deposit sediment from slow-moving water
+    fn erode(&mut self, elevation: &mut Elevation) {
+        let settings = &self.settings;
+
+        for idx in 0..self.water.len() {
+            let (x, y) = self.water.from_idx(idx);
+
+            // Sine of the local tilt angle, from the slope of the terrain
+            let slope = elevation
+                .gradient_at(f64::from(x), f64::from(y))
+                .magnitude()
+                * HEIGHT_SCALE;
+            let tilt = (slope / (1.0 + slope * slope).sqrt()).max(settings.min_tilt);
+
+            let depth = (self.water[idx] / EROSION_DEPTH).min(1.0);
+
+            // Water slows to a stop on reaching the ocean, dropping everything it carries
+            let capacity = if elevation[idx] > SEA_LEVEL {
+                settings.capacity * tilt * self.velocity[idx].magnitude() * depth
+            } else {
+                0.0
+            };
+            let sediment = self.sediment[idx];
+            let amount = if capacity > sediment {
+                // Never dig below our lowest neighbor; flowing water can't carve a pit
+                let floor = elevation
+                    .neighbors4(x, y)
+                    .map(|neighbor| elevation[neighbor])
+                    .fold(f64::INFINITY, f64::min);
+                let room = (elevation[idx] - floor).max(0.0);
+
+                (settings.dissolving * (capacity - sediment)).min(room)
+            } else {
+                -settings.deposition * (sediment - capacity)
+            };
+
+            elevation[idx] -= amount;
+            self.sediment[idx] += amount;
+        }
+    }
+
+    /// Carry suspended sediment along with the water's velocity
+    fn transport_sediment(&mut self) {
+        let dt = self.settings.dt;
+
+        // Trace each cell backwards along the velocity field to find where its sediment came from
+        let sediment = Grid::from_fn(self.sediment.size(), |x, y| {
+            let velocity = self.velocity[(x, y)];
+            self.sediment.sample_bilinear(
+                f64::from(x) - velocity.x * dt,
+                f64::from(y) - velocity.y * dt,
+            )
+        });
+
+        self.sediment = sediment;
+    }
+
+    fn evaporate(&mut self) {
+        let evaporation = 1.0 - self.settings.dt * self.settings.evaporation;
+
+        for water in self.water.iter_mut() {
+            *water *= evaporation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn water_runs_downhill() {
+        // A ramp rising away from a strip of ocean along the left edge
+        let grid = Grid::from_fn(16, |x, _| f64::from(x) / 16.0 - 0.1);
+        let mut elevation = Elevation::from(grid);
+        let mut sim = ShallowWater::new(16, Default::default());
+
+        sim.run(&mut elevation, 200);
+
+        assert!(sim.water().iter().all(|w| w.is_finite() && *w >= 0.0));
+        assert!(elevation.iter().all(|h| h.is_finite()));
+        // Water on the slopes should be moving towards the ocean
+        let velocity = sim.velocity()[(8, 8)];
+        assert!(velocity.x < 0.0, "Velocity {:?} isn't downhill", velocity);
+    }
+}
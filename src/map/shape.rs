@@ -0,0 +1,110 @@
+//! Island shapes
+//!
+//! A shape sets the overall outline of our land before noise adds the detail, and each shape
+//! models a different way islands form: the layered `Gradient` gives a single irregular island,
+//! while the others model volcanic cones, coral atolls, hotspot chains, and clusters of islands.
+//! A `Mask` lets the user draw the outline themselves.
+
+use super::gradient::Gradient;
+use rand_xoshiro::Xoshiro256StarStar;
+
+mod atoll;
+mod chain;
+mod cluster;
+mod mask;
+mod volcano;
+
+pub use atoll::Atoll;
+pub use chain::Chain;
+pub use cluster::Cluster;
+pub use mask::Mask;
+pub use volcano::Volcano;
+
+/// Defines the overall shape of an island
+pub trait IslandShape {
+    /// Get the shape's value at point (x, y), where both lie in the range [0.0, 1.0].
+    ///
+    /// # Returns
+    ///
+    /// A value within the closed range [0.0, 1.0]; higher values are more likely to be land, and
+    /// values should fall to 0.0 towards the edges of the map.
+    fn at(&self, x: f64, y: f64) -> f64;
+}
+
+/// The kinds of island shapes we can generate
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShapeKind {
+    /// A single irregular island built from layered gradients
+    #[default]
+    Gradient,
+    /// A single volcanic cone with a caldera at its peak
+    Volcano,
+    /// A ring of low islands around a lagoon
+    Atoll,
+    /// A chain of islands along a hotspot track, shrinking as they age
+    Chain,
+    /// A cluster of several islands
+    Cluster,
+}
+
+impl ShapeKind {
+    /// Generate a random shape of this kind
+    pub fn generate(self, rng: &mut Xoshiro256StarStar) -> Box<dyn IslandShape> {
+        match self {
+            Self::Gradient => Box::new(Gradient::new(rng, 4)),
+            Self::Volcano => Box::new(Volcano::new(rng)),
+            Self::Atoll => Box::new(Atoll::new(rng)),
+            Self::Chain => Box::new(Chain::new(rng)),
+            Self::Cluster => Box::new(Cluster::new(rng)),
+        }
+    }
+}
+
+/// A cone of the given radius around (cx, cy), with flanks that steepen towards its peak
+///
+/// Returns a value in the range [0.0, 1.0], reaching 1.0 at the center.
+fn cone(cx: f64, cy: f64, radius: f64, x: f64, y: f64) -> f64 {
+    let dist = ((x - cx).powi(2) + (y - cy).powi(2)).sqrt();
+
+    (1.0 - dist / radius).max(0.0).powf(1.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn shapes_stay_in_range() {
+        let kinds = [
+            ShapeKind::Gradient,
+            ShapeKind::Volcano,
+            ShapeKind::Atoll,
+            ShapeKind::Chain,
+            ShapeKind::Cluster,
+        ];
+
+        for seed in 0..5 {
+            let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
+            for kind in kinds.iter() {
+                let shape = kind.generate(&mut rng);
+
+                let mut max: f64 = 0.0;
+                for y in 0..=50 {
+                    for x in 0..=50 {
+                        let (x, y) = (f64::from(x) / 50.0, f64::from(y) / 50.0);
+                        let value = shape.at(x, y);
+                        assert!((0.0..=1.0).contains(&value), "{:?} gave {}", kind, value);
+                        max = max.max(value);
+                    }
+                }
+                assert!(max > 0.5, "{:?} has no land", kind);
+
+                // The corners of the map are always open ocean
+                assert!(shape.at(0.0, 0.0) < 0.1, "{:?} reaches the corner", kind);
+                assert!(shape.at(1.0, 1.0) < 0.1, "{:?} reaches the corner", kind);
+            }
+        }
+    }
+}
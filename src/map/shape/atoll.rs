@@ -0,0 +1,55 @@
+use super::IslandShape;
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+use std::f64::consts::{PI, TAU};
+
+/// A ring of coral islands around a central lagoon
+///
+/// The ring is broken by passes so the lagoon stays open to the sea.
+#[derive(Debug)]
+pub struct Atoll {
+    x: f64,
+    y: f64,
+    radius: f64,
+    width: f64,
+    /// Angles of the passes through the ring into the lagoon
+    passes: Vec<f64>,
+}
+
+impl Atoll {
+    pub fn new(rng: &mut Xoshiro256StarStar) -> Self {
+        let passes = (0..rng.gen_range(1..=3))
+            .map(|_| rng.gen_range(0.0..TAU))
+            .collect();
+
+        Self {
+            x: rng.gen_range(0.45..0.55),
+            y: rng.gen_range(0.45..0.55),
+            radius: rng.gen_range(0.22..0.3),
+            width: rng.gen_range(0.012..0.02),
+            passes,
+        }
+    }
+}
+
+impl IslandShape for Atoll {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        let (dx, dy) = (x - self.x, y - self.y);
+        let dist = (dx * dx + dy * dy).sqrt();
+
+        // The reef is a low ridge running around the lagoon, low enough that our noise will break
+        // it up into separate islets
+        let ring = 0.75 * (-((dist - self.radius) / self.width).powi(2)).exp();
+
+        // Cut our passes through it
+        let angle = dy.atan2(dx);
+        let pass = self.passes.iter().fold(1.0, |pass: f64, &gap| {
+            // Angular distance to the pass, wrapped into [0, π]
+            let diff = ((angle - gap).rem_euclid(TAU) - PI).abs();
+            let diff = PI - diff;
+            pass.min(1.0 - (-(diff * 8.0).powi(2)).exp())
+        });
+
+        ring * pass
+    }
+}
@@ -0,0 +1,53 @@
+use super::{cone, IslandShape};
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+use std::f64::consts::TAU;
+
+/// A chain of volcanic islands left behind as the sea floor drifts over a hotspot
+///
+/// The youngest island, still over the hotspot, is the largest; older islands further along the
+/// track have eroded and subsided into smaller ones.
+#[derive(Debug)]
+pub struct Chain {
+    /// Each island's center and radius, from youngest to oldest
+    islands: Vec<(f64, f64, f64)>,
+}
+
+impl Chain {
+    pub fn new(rng: &mut Xoshiro256StarStar) -> Self {
+        let count = rng.gen_range(3..=6);
+        // The track crosses the middle of the map
+        let angle = rng.gen_range(0.0..TAU);
+        let (dx, dy) = (angle.cos(), angle.sin());
+        let length = 0.6;
+        let step = length / f64::from(count - 1);
+
+        let mut radius = rng.gen_range(0.16..0.2);
+        let islands = (0..count)
+            .map(|i| {
+                let t = f64::from(i) * step - length / 2.0;
+                // Let the track wander a little, as real ones do
+                let jitter = rng.gen_range(-0.03..0.03);
+                let island = (
+                    0.5 + dx * t - dy * jitter,
+                    0.5 + dy * t + dx * jitter,
+                    radius,
+                );
+                radius *= rng.gen_range(0.6..0.8);
+
+                island
+            })
+            .collect();
+
+        Self { islands }
+    }
+}
+
+impl IslandShape for Chain {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        self.islands
+            .iter()
+            .map(|&(cx, cy, radius)| cone(cx, cy, radius, x, y))
+            .fold(0.0, f64::max)
+    }
+}
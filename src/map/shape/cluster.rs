@@ -0,0 +1,36 @@
+use super::{cone, IslandShape};
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+use std::f64::consts::TAU;
+
+/// A loose cluster of islands of varying sizes
+#[derive(Debug)]
+pub struct Cluster {
+    /// Each island's center and radius
+    islands: Vec<(f64, f64, f64)>,
+}
+
+impl Cluster {
+    pub fn new(rng: &mut Xoshiro256StarStar) -> Self {
+        let islands = (0..rng.gen_range(3..=7))
+            .map(|_| {
+                let angle = rng.gen_range(0.0..TAU);
+                let dist = rng.gen_range(0.0..0.25);
+                let radius = rng.gen_range(0.07..0.15);
+
+                (0.5 + dist * angle.cos(), 0.5 + dist * angle.sin(), radius)
+            })
+            .collect();
+
+        Self { islands }
+    }
+}
+
+impl IslandShape for Cluster {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        self.islands
+            .iter()
+            .map(|&(cx, cy, radius)| cone(cx, cy, radius, x, y))
+            .fold(0.0, f64::max)
+    }
+}
@@ -0,0 +1,200 @@
+use super::IslandShape;
+use crate::map::math::ramp;
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
+use image::GrayImage;
+use std::path::Path;
+
+/// Width of the band around the edge of the map over which a mask fades out, as a fraction of
+/// the map size
+///
+/// This keeps a border of open ocean around the island no matter what the mask holds.
+const EDGE_FALLOFF: f64 = 0.08;
+
+/// An island outline supplied by the user, either as a grayscale image or as a polygon
+///
+/// Brighter pixels, or points inside the polygon, are more likely to be land.
+#[derive(Debug, Clone)]
+pub enum Mask {
+    Image {
+        width: u32,
+        height: u32,
+        /// Pixel values in the range [0.0, 1.0], in rows
+        pixels: Vec<f64>,
+    },
+    Polygon {
+        /// Vertices of the polygon, with coordinates in the range [0.0, 1.0]
+        points: Vec<(f64, f64)>,
+        /// Distance over which the polygon's edges blend into the sea, as a fraction of the map
+        falloff: f64,
+    },
+}
+
+#[allow(dead_code)]
+impl Mask {
+    /// Create a mask from a grayscale image, which is stretched to cover the whole map
+    ///
+    /// The image must have at least one pixel.
+    pub fn from_image(image: &GrayImage) -> image::ImageResult<Self> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+
+        Ok(Self::Image {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.pixels().map(|p| f64::from(p.0[0]) / 255.0).collect(),
+        })
+    }
+
+    /// Load a mask from an image file
+    pub fn open<P: AsRef<Path>>(path: P) -> image::ImageResult<Self> {
+        Self::from_image(&image::open(path)?.to_luma8())
+    }
+
+    /// Create a mask from the outline of a polygon
+    pub fn from_polygon(points: Vec<(f64, f64)>, falloff: f64) -> Self {
+        Self::Polygon {
+            points,
+            falloff: falloff.max(f64::EPSILON),
+        }
+    }
+
+    /// Blend this mask with another shape
+    ///
+    /// A `weight` of 1.0 uses only the mask, while lower weights mix in more of `shape`.
+    pub fn blend(self, shape: Box<dyn IslandShape>, weight: f64) -> Blend {
+        Blend {
+            mask: self,
+            shape,
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+
+    /// The mask's own value at (x, y), before fading out at the edges of the map
+    fn value(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Self::Image {
+                width,
+                height,
+                pixels,
+            } => {
+                let pixel = |px: u32, py: u32| pixels[(px + py * width) as usize];
+
+                // Bilinear sampling, so small masks don't give us blocky islands
+                let px = x.clamp(0.0, 1.0) * f64::from(width - 1);
+                let py = y.clamp(0.0, 1.0) * f64::from(height - 1);
+                let (x0, y0) = (px.floor() as u32, py.floor() as u32);
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let (tx, ty) = (px.fract(), py.fract());
+
+                let top = pixel(x0, y0) * (1.0 - tx) + pixel(x1, y0) * tx;
+                let bottom = pixel(x0, y1) * (1.0 - tx) + pixel(x1, y1) * tx;
+                top * (1.0 - ty) + bottom * ty
+            }
+            Self::Polygon { points, falloff } => {
+                let dist = distance_to_outline(points, x, y) / falloff;
+                if contains(points, x, y) {
+                    0.5 + 0.5 * dist.min(1.0)
+                } else {
+                    0.5 * (1.0 - dist).max(0.0)
+                }
+            }
+        }
+    }
+}
+
+impl IslandShape for Mask {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        let edge = x.min(1.0 - x).min(y).min(1.0 - y);
+        let fade = ramp(0.0, EDGE_FALLOFF, edge);
+
+        self.value(x, y).clamp(0.0, 1.0) * fade
+    }
+}
+
+/// A mask blended with another shape
+pub struct Blend {
+    mask: Mask,
+    shape: Box<dyn IslandShape>,
+    weight: f64,
+}
+
+impl IslandShape for Blend {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        let mask = self.mask.at(x, y);
+        if self.weight >= 1.0 {
+            return mask;
+        }
+
+        mask * self.weight + self.shape.at(x, y) * (1.0 - self.weight)
+    }
+}
+
+/// Test whether (x, y) falls inside the polygon, using the even-odd rule
+fn contains(points: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    let mut j = points.len().wrapping_sub(1);
+    for (i, &(xi, yi)) in points.iter().enumerate() {
+        let (xj, yj) = points[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+/// Find the distance from (x, y) to the nearest edge of the polygon
+fn distance_to_outline(points: &[(f64, f64)], x: f64, y: f64) -> f64 {
+    let mut min = f64::INFINITY;
+    for (i, &(x1, y1)) in points.iter().enumerate() {
+        let (x2, y2) = points[(i + 1) % points.len()];
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let len2 = dx * dx + dy * dy;
+
+        // Project our point onto the edge, clamping to its ends
+        let t = if len2 > 0.0 {
+            (((x - x1) * dx + (y - y1) * dy) / len2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (px, py) = (x1 + t * dx, y1 + t * dy);
+
+        min = min.min(((x - px).powi(2) + (y - py).powi(2)).sqrt());
+    }
+
+    min
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon_mask() {
+        let mask = Mask::from_polygon(vec![(0.3, 0.3), (0.7, 0.3), (0.7, 0.7), (0.3, 0.7)], 0.1);
+
+        assert_eq!(mask.at(0.5, 0.5), 1.0);
+        assert!((mask.at(0.3, 0.5) - 0.5).abs() < 1e-9);
+        assert_eq!(mask.at(0.1, 0.5), 0.0);
+    }
+
+    #[test]
+    fn image_mask_fades_at_edges() {
+        // A completely white mask would cover the map in land, if not for the edge falloff
+        let mask = Mask::from_image(&GrayImage::from_pixel(8, 8, image::Luma([255]))).unwrap();
+
+        assert_eq!(mask.at(0.5, 0.5), 1.0);
+        assert_eq!(mask.at(0.0, 0.5), 0.0);
+        assert_eq!(mask.at(0.5, 1.0), 0.0);
+    }
+
+    #[test]
+    fn empty_image_masks_are_rejected() {
+        assert!(Mask::from_image(&GrayImage::new(0, 0)).is_err());
+        assert!(Mask::from_image(&GrayImage::new(4, 0)).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use super::{cone, IslandShape};
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+
+/// A single volcanic cone, its peak collapsed into a caldera
+#[derive(Debug)]
+pub struct Volcano {
+    x: f64,
+    y: f64,
+    radius: f64,
+    /// Radius of the caldera, as a fraction of the cone's radius
+    caldera: f64,
+    /// Depth of the caldera, as a fraction of the cone's height
+    depth: f64,
+}
+
+impl Volcano {
+    pub fn new(rng: &mut Xoshiro256StarStar) -> Self {
+        Self {
+            x: rng.gen_range(0.45..0.55),
+            y: rng.gen_range(0.45..0.55),
+            radius: rng.gen_range(0.3..0.38),
+            caldera: rng.gen_range(0.12..0.22),
+            depth: rng.gen_range(0.2..0.4),
+        }
+    }
+}
+
+impl IslandShape for Volcano {
+    fn at(&self, x: f64, y: f64) -> f64 {
+        let height = cone(self.x, self.y, self.radius, x, y);
+
+        // Within the caldera the floor sinks into a bowl below the rim
+        let dist = ((x - self.x).powi(2) + (y - self.y).powi(2)).sqrt();
+        let caldera = self.radius * self.caldera;
+        if dist < caldera {
+            let rim = cone(self.x, self.y, self.radius, self.x + caldera, self.y);
+            let bowl = 1.0 - (dist / caldera).powi(2);
+
+            rim - self.depth * bowl
+        } else {
+            height
+        }
+    }
+}
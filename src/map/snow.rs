@@ -0,0 +1,229 @@
+//! Snow, ice, and glaciers on our highest peaks
+//!
+//! We have no climate to give us temperatures, so the cold comes from height alone: snow lies
+//! above the snow line, fading out through a band below it, but slides off faces too steep to
+//! hold it. Above the equilibrium line, snow builds up into glaciers faster than it melts; these
+//! flow down the valleys, grinding them into broad U-shaped troughs until they melt away below.
+
+use super::drainage::Drainage;
+use super::elevation::{Elevation, HEIGHT_SCALE};
+use super::grid::Grid;
+use super::math::ramp;
+use super::SEA_LEVEL;
+use std::f64::consts::SQRT_2;
+
+/// Parameters for where snow lies
+#[derive(Debug, Clone)]
+pub struct Snow {
+    /// Height above which the ground is fully covered in snow
+    pub snow_line: f64,
+    /// Depth of the band below the snow line through which snow thins out
+    pub transition: f64,
+    /// Slope, in degrees, above which snow begins to slide away
+    pub shed_angle: f64,
+    /// Slope, in degrees, above which no snow can lie at all
+    pub bare_angle: f64,
+}
+
+impl Default for Snow {
+    fn default() -> Self {
+        Self {
+            snow_line: 0.8,
+            transition: 0.1,
+            shed_angle: 30.0,
+            bare_angle: 50.0,
+        }
+    }
+}
+
+impl Snow {
+    /// How thickly (x, y) is covered in snow and ice, from 0.0 for bare ground to 1.0
+    ///
+    /// `ice` gives the cover of glaciers, which lie regardless of the slope.
+    pub fn at(&self, elevation: &Elevation, ice: &Grid<f64>, x: u32, y: u32) -> f64 {
+        let height = elevation[(x, y)];
+        if height <= SEA_LEVEL {
+            return 0.0;
+        }
+
+        let altitude = ramp(self.snow_line - self.transition, self.snow_line, height);
+        let angle = elevation.get_normal(x, y).z.abs().acos().to_degrees();
+        let hold = 1.0 - ramp(self.shed_angle, self.bare_angle, angle);
+
+        (altitude * hold).max(ice[(x, y)])
+    }
+}
+
+/// Parameters for the glacier simulation
+#[derive(Debug, Clone)]
+pub struct Glaciers {
+    /// Height above which snow builds up into ice faster than it melts
+    pub equilibrium_line: f64,
+    /// Ice gained in each step for each unit of height above the equilibrium line
+    pub accumulation: f64,
+    /// Ice lost in each step for each unit of height below the equilibrium line
+    pub ablation: f64,
+    /// Depth a glacier carves into its bed in each step, for each unit of the square root of the
+    /// ice flowing through it, on a 45 degree slope; glaciers on gentler slopes slide more slowly
+    /// and carve less
+    pub erosion: f64,
+    /// Least ice that must flow through a cell for it to carve its bed
+    pub min_flux: f64,
+    /// Distance, in cells, from a glacier's center to the walls of its trough, for each unit of
+    /// the square root of the ice flowing through it
+    pub width: f64,
+    /// Number of steps to simulate
+    pub iterations: u32,
+}
+
+impl Default for Glaciers {
+    fn default() -> Self {
+        Self {
+            equilibrium_line: 0.65,
+            accumulation: 0.01,
+            ablation: 1.0,
+            erosion: 0.002,
+            min_flux: 1.0,
+            width: 2.0,
+            iterations: 10,
+        }
+    }
+}
+
+impl Glaciers {
+    /// Let glaciers flow down our valleys and carve them out, returning how thickly each cell is
+    /// covered in ice at the end, from 0.0 to 1.0
+    pub fn run(&self, elevation: &mut Elevation) -> Grid<f64> {
+        let size = elevation.size();
+        let mut ice = Grid::new(size, 0.0);
+
+        for _ in 0..self.iterations {
+            let drainage = Drainage::new(elevation);
+            let flux = self.flow(elevation, &drainage);
+
+            // Each glacier grinds out a trough around itself, with a broad floor and steep walls
+            let mut carved = elevation.clone();
+            ice = Grid::new(size, 0.0);
+            for ((x, y), &flux) in flux.cells() {
+                let idx = elevation.to_idx(x, y);
+                if flux <= 0.0 || elevation[idx] <= SEA_LEVEL {
+                    continue;
+                }
+
+                // Glaciers slide faster, and so carve deeper, down steeper slopes
+                let slope = drainage.receiver(idx).map_or(0.0, |receiver| {
+                    let (rx, ry) = elevation.from_idx(receiver);
+                    let run = if rx != x && ry != y { SQRT_2 } else { 1.0 };
+                    ((elevation[idx] - elevation[receiver]) / run * HEIGHT_SCALE).clamp(0.0, 1.0)
+                });
+                let depth = if flux >= self.min_flux {
+                    self.erosion * flux.sqrt() * slope
+                } else {
+                    0.0
+                };
+
+                let floor = elevation[idx] - depth;
+                let width = 1.0 + self.width * flux.sqrt();
+                let reach = width.ceil() as i64;
+                for dy in -reach..=reach {
+                    for dx in -reach..=reach {
+                        let (nx, ny) = (i64::from(x) + dx, i64::from(y) + dy);
+                        if !elevation.in_bounds(nx, ny) {
+                            continue;
+                        }
+                        let cell = (nx as u32, ny as u32);
+                        let dist = ((dx * dx + dy * dy) as f64).sqrt() / width;
+                        if dist >= 1.0 || elevation[cell] <= SEA_LEVEL {
+                            continue;
+                        }
+
+                        let wall = floor + (elevation[cell] - floor).max(0.0) * dist.powi(4);
+                        carved[cell] = carved[cell].min(wall);
+                        ice[cell] = f64::max(ice[cell], 1.0 - dist.powi(4));
+                    }
+                }
+            }
+
+            for (height, &new) in elevation.iter_mut().zip(carved.iter()) {
+                *height = height.min(new);
+            }
+        }
+
+        ice
+    }
+
+    /// Find how much ice flows through each cell, following the drainage downhill
+    fn flow(&self, elevation: &Elevation, drainage: &Drainage) -> Grid<f64> {
+        let mut flux = Grid::new(elevation.size(), 0.0);
+
+        // Walk the stack backwards to work down from the peaks, gathering ice as we go
+        for &idx in drainage.stack().iter().rev() {
+            let height = elevation[idx];
+            let balance = if height > self.equilibrium_line {
+                self.accumulation * (height - self.equilibrium_line)
+            } else {
+                -self.ablation * (self.equilibrium_line - height)
+            };
+            flux[idx] = f64::max(flux[idx] + balance, 0.0);
+
+            if let Some(receiver) = drainage.receiver(idx) {
+                flux[receiver] += flux[idx];
+            }
+        }
+
+        flux
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A broad dome rising from the sea to a height of 1.0
+    fn dome() -> Elevation {
+        Elevation::from(Grid::from_fn(80, |x, y| {
+            let dist = (f64::from(x) - 40.0).hypot(f64::from(y) - 40.0);
+            1.0 - (dist / 35.0).powi(2)
+        }))
+    }
+
+    #[test]
+    fn snow_lies_on_high_gentle_ground() {
+        let elevation = dome();
+        let ice = Grid::new(80, 0.0);
+        let snow = Snow::default();
+
+        assert!(snow.at(&elevation, &ice, 48, 40) > 0.9);
+        assert_eq!(snow.at(&elevation, &ice, 60, 40), 0.0);
+
+        // Steep faces shed their snow, however high they are
+        let steep = Snow {
+            shed_angle: 10.0,
+            bare_angle: 20.0,
+            ..Default::default()
+        };
+        assert_eq!(steep.at(&elevation, &ice, 48, 40), 0.0);
+    }
+
+    #[test]
+    fn glaciers_carve_below_the_peaks() {
+        let mut elevation = dome();
+        let before = elevation.clone();
+
+        let ice = Glaciers::default().run(&mut elevation);
+
+        // Glaciers only ever wear the land down, and leave the lowlands untouched
+        let mut carved = false;
+        for ((x, y), &height) in before.cells() {
+            assert!(elevation[(x, y)] <= height);
+            carved |= elevation[(x, y)] < height;
+            if height < 0.3 {
+                assert_eq!(elevation[(x, y)], height);
+            }
+        }
+        assert!(carved);
+
+        // The lowlands are left free of ice
+        assert_eq!(ice[(40, 72)], 0.0);
+    }
+}
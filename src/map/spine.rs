@@ -0,0 +1,251 @@
+//! Mountain ranges along spline "spines"
+//!
+//! A spine is a Catmull-Rom spline through a handful of control points, and carries a range of
+//! ridged mountains along its length. Ranges are highest along the crest of the spine and fall
+//! away to either side over `width`, and taper off over `taper` towards either end, so that the
+//! island gets a mountainous backbone rather than a single central peak.
+
+use super::grid::cubic;
+use super::math::smoothstep;
+use super::noise::Fractal;
+use bracket_noise::prelude::*;
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+use std::f64::consts::TAU;
+
+/// Number of line segments we approximate each span of our spline with
+const SAMPLES_PER_SPAN: usize = 16;
+
+/// A mountain range following a curve across the island
+#[derive(Debug, Clone)]
+pub struct Spine {
+    /// Control points of the spine, in the same [0.0, 1.0] coordinates as our shapes
+    pub points: Vec<(f64, f64)>,
+    /// Distance from the crest at which the range meets the lowlands
+    pub width: f64,
+    /// Height added along the crest at the middle of the range
+    pub height: f64,
+    /// Fraction of the spine's length at each end over which the range tapers away
+    pub taper: f64,
+    /// Shape of the range's cross-section; higher values give narrower, sharper crests
+    pub sharpness: f64,
+    /// The ridged noise that breaks the range into individual peaks
+    pub ridges: Fractal,
+}
+
+impl Default for Spine {
+    fn default() -> Self {
+        Self {
+            points: Vec::new(),
+            width: 0.08,
+            height: 0.35,
+            taper: 0.3,
+            sharpness: 1.5,
+            ridges: Fractal {
+                frequency: 6.0,
+                octaves: 4,
+                gain: 0.5,
+                lacunarity: 2.0,
+            },
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Spine {
+    /// Create a spine through the given control points, with our default profile
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        Self {
+            points,
+            ..Default::default()
+        }
+    }
+
+    /// Generate a random spine running across the middle of the map
+    pub fn random(rng: &mut Xoshiro256StarStar) -> Self {
+        let angle = rng.gen_range(0.0..TAU);
+        let length = rng.gen_range(0.25..0.45);
+        let center = (
+            0.5 + rng.gen_range(-0.08..0.08),
+            0.5 + rng.gen_range(-0.08..0.08),
+        );
+
+        // Lay our control points out along a line, then bend it by pushing each to one side
+        let (dx, dy) = (angle.cos(), angle.sin());
+        let points = (0..5)
+            .map(|i| {
+                let along = (f64::from(i) / 4.0 - 0.5) * length;
+                let across = rng.gen_range(-0.05..0.05);
+
+                (
+                    center.0 + along * dx - across * dy,
+                    center.1 + along * dy + across * dx,
+                )
+            })
+            .collect();
+
+        Self {
+            points,
+            width: rng.gen_range(0.06..0.1),
+            height: rng.gen_range(0.25..0.45),
+            ..Default::default()
+        }
+    }
+
+    /// Prepare this spine to be sampled, seeding its ridged noise with `seed`
+    pub fn build(&self, seed: u64) -> Range {
+        let mut ridges = FastNoise::seeded(seed);
+        ridges.set_noise_type(NoiseType::SimplexFractal);
+        ridges.set_fractal_type(FractalType::RigidMulti);
+        ridges.set_fractal_octaves(self.ridges.octaves as i32);
+        ridges.set_fractal_gain(self.ridges.gain as f32);
+        ridges.set_fractal_lacunarity(self.ridges.lacunarity as f32);
+        ridges.set_frequency(self.ridges.frequency as f32);
+
+        // Flatten our spline into a polyline, remembering the distance along it of each vertex
+        let mut line = Vec::new();
+        let mut length = 0.0;
+        for span in 0..self.points.len().saturating_sub(1) {
+            let point = |i: usize| self.points[i.clamp(0, self.points.len() - 1)];
+            let (p0, p1, p2, p3) = (
+                point(span.max(1) - 1),
+                point(span),
+                point(span + 1),
+                point(span + 2),
+            );
+
+            for i in 0..SAMPLES_PER_SPAN {
+                let t = i as f64 / SAMPLES_PER_SPAN as f64;
+                let next = (
+                    cubic(p0.0, p1.0, p2.0, p3.0, t),
+                    cubic(p0.1, p1.1, p2.1, p3.1, t),
+                );
+                if let Some(&(prev, _)) = line.last() {
+                    length += distance(prev, next);
+                }
+                line.push((next, length));
+            }
+        }
+        if let Some(&last) = self.points.last() {
+            if let Some(&(prev, _)) = line.last() {
+                length += distance(prev, last);
+            }
+            line.push((last, length));
+        }
+
+        // Store positions along the spine as a fraction of its length
+        if length > 0.0 {
+            for (_, along) in line.iter_mut() {
+                *along /= length;
+            }
+        }
+
+        let (mut min, mut max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+        for &((x, y), _) in line.iter() {
+            min = (min.0.min(x - self.width), min.1.min(y - self.width));
+            max = (max.0.max(x + self.width), max.1.max(y + self.width));
+        }
+
+        Range {
+            line,
+            min,
+            max,
+            width: self.width,
+            height: self.height,
+            taper: self.taper,
+            sharpness: self.sharpness,
+            ridges,
+        }
+    }
+}
+
+/// A spine ready to be sampled
+pub struct Range {
+    /// Vertices of our polyline, each with its fractional distance along the spine
+    line: Vec<((f64, f64), f64)>,
+    /// Bounding box of everything our range can reach
+    min: (f64, f64),
+    max: (f64, f64),
+    width: f64,
+    height: f64,
+    taper: f64,
+    sharpness: f64,
+    ridges: FastNoise,
+}
+
+impl Range {
+    /// Height of this range at point (x, y)
+    pub fn at(&self, x: f64, y: f64) -> f64 {
+        if x < self.min.0 || y < self.min.1 || x > self.max.0 || y > self.max.1 {
+            return 0.0;
+        }
+
+        // Find the nearest point on our spine, and how far along it that point lies
+        let (dist, along) = self
+            .line
+            .windows(2)
+            .map(|segment| {
+                let ((a, ta), (b, tb)) = (segment[0], segment[1]);
+                let (abx, aby) = (b.0 - a.0, b.1 - a.1);
+                let len2 = abx * abx + aby * aby;
+                let t = if len2 > 0.0 {
+                    (((x - a.0) * abx + (y - a.1) * aby) / len2).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+
+                (
+                    distance((a.0 + abx * t, a.1 + aby * t), (x, y)),
+                    ta + (tb - ta) * t,
+                )
+            })
+            .fold((f64::MAX, 0.0), |nearest, candidate| {
+                if candidate.0 < nearest.0 {
+                    candidate
+                } else {
+                    nearest
+                }
+            });
+        if dist >= self.width {
+            return 0.0;
+        }
+
+        // Fall away from the crest, and taper off towards the ends
+        let across = smoothstep(1.0 - dist / self.width).powf(self.sharpness);
+        let lengthwise = if self.taper > 0.0 {
+            smoothstep((along.min(1.0 - along) / self.taper).min(1.0))
+        } else {
+            1.0
+        };
+
+        // Ridged noise lies roughly within [-1.0, 1.0]; keep a solid base beneath the peaks
+        let ridges = (f64::from(self.ridges.get_noise(x as f32, y as f32)) + 1.0) / 2.0;
+        let ridges = 0.4 + 0.6 * ridges.clamp(0.0, 1.0);
+
+        self.height * across * lengthwise * ridges
+    }
+}
+
+#[inline(always)]
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_follow_the_spine() {
+        let range = Spine::new(vec![(0.2, 0.5), (0.5, 0.45), (0.8, 0.5)]).build(7);
+
+        // Highest along the middle of the crest...
+        let crest = range.at(0.5, 0.45);
+        assert!(crest > 0.0);
+        assert!(crest > range.at(0.5, 0.5));
+        // ...tapering to nothing at the ends and beyond our width
+        assert!(range.at(0.2, 0.5) < 1e-9);
+        assert_eq!(range.at(0.5, 0.6), 0.0);
+        assert_eq!(range.at(0.05, 0.05), 0.0);
+    }
+}
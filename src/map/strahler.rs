@@ -0,0 +1,60 @@
+/// Strahler Number
+///
+/// https://en.wikipedia.org/wiki/Strahler_number#River_networks
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Strahler(u32);
+
+impl Strahler {
+    /// The order of a stream fed by the given streams
+    ///
+    /// A stream with nothing flowing into it is a source, of order 1. Otherwise it takes the
+    /// highest order among its inflows, one higher if two or more of them share that order.
+    pub fn confluence<I: IntoIterator<Item = Strahler>>(inflows: I) -> Strahler {
+        let (max, count) = inflows
+            .into_iter()
+            .fold((0, 0), |(max, count), Strahler(order)| {
+                match order.cmp(&max) {
+                    std::cmp::Ordering::Greater => (order, 1),
+                    std::cmp::Ordering::Equal => (max, count + 1),
+                    std::cmp::Ordering::Less => (max, count),
+                }
+            });
+
+        match (max, count) {
+            (0, _) => Strahler(1),
+            (max, 1) => Strahler(max),
+            (max, _) => Strahler(max + 1),
+        }
+    }
+}
+
+impl From<u32> for Strahler {
+    fn from(from: u32) -> Strahler {
+        Strahler(from)
+    }
+}
+
+impl From<Strahler> for u32 {
+    fn from(from: Strahler) -> u32 {
+        from.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confluence_follows_the_definition() {
+        let orders = |orders: &[u32]| Strahler::confluence(orders.iter().map(|&o| o.into()));
+
+        assert_eq!(orders(&[]), Strahler(1));
+        assert_eq!(orders(&[1]), Strahler(1));
+        assert_eq!(orders(&[1, 1]), Strahler(2));
+        assert_eq!(orders(&[2, 1]), Strahler(2));
+        // Two lesser streams never add up to a greater one
+        assert_eq!(orders(&[1, 1, 2]), Strahler(2));
+        assert_eq!(orders(&[2, 3, 2]), Strahler(3));
+        assert_eq!(orders(&[3, 2, 3, 3]), Strahler(4));
+    }
+}
@@ -0,0 +1,157 @@
+//! Simulate landscape evolution under the stream power law
+//!
+//! Rather than weathering a fixed landscape, the terrain is pushed up by tectonic uplift while
+//! rivers cut down into it at a rate proportional to their slope and drainage area. Over many
+//! steps this settles into networks of ridges and valleys.
+//!
+//! The incision is solved implicitly, following Braun and Willett's "A very efficient O(n),
+//! implicit and parallel method to solve the stream power equation governing fluvial incision and
+//! landscape evolution" (2013).
+
+use super::drainage::Drainage;
+use super::elevation::Elevation;
+use super::grid::Grid;
+use super::shape::IslandShape;
+use super::SEA_LEVEL;
+
+/// Parameters for the stream power law
+#[derive(Debug, Clone)]
+pub struct StreamPower {
+    /// Time scale ("Delta Time") of each step
+    pub dt: f64,
+    /// Erodibility of the terrain, the "K" in the stream power law
+    pub erodibility: f64,
+    /// Exponent applied to the drainage area, the "m" in the stream power law
+    pub area_exponent: f64,
+    /// Uplift per unit of time where the uplift field is at its strongest
+    pub uplift_rate: f64,
+}
+
+impl Default for StreamPower {
+    fn default() -> Self {
+        Self {
+            dt: 1.0,
+            erodibility: 0.0005,
+            area_exponent: 0.5,
+            uplift_rate: 0.001,
+        }
+    }
+}
+
+impl StreamPower {
+    /// Build an uplift field from our island's shape, so uplift is strongest in its core
+    pub fn uplift_from(shape: &dyn IslandShape, size: u32) -> Grid<f64> {
+        let scale = f64::from(size);
+
+        Grid::from_fn(size, |x, y| {
+            shape.at(f64::from(x) / scale, f64::from(y) / scale)
+        })
+    }
+
+    /// Evolve the landscape for the given number of time steps
+    ///
+    /// `uplift` scales `uplift_rate` in each cell, and should be in the range [0.0, 1.0].
+    pub fn evolve(&self, elevation: &mut Elevation, uplift: &Grid<f64>, steps: u32) {
+        let dt = self.dt;
+
+        for _ in 0..steps {
+            // Rivers move as the terrain changes, so re-route our drainage each step
+            let drainage = Drainage::new(elevation);
+            let area = drainage.area();
+
+            for &idx in drainage.stack() {
+                // The ocean is our base level and never moves
+                if elevation[idx] <= SEA_LEVEL {
+                    continue;
+                }
+
+                elevation[idx] += dt * self.uplift_rate * uplift[idx];
+
+                let receiver = match drainage.receiver(idx) {
+                    Some(receiver) => receiver,
+                    None => continue,
+                };
+                // Our receiver has already been updated, so we can solve for our new height
+                // directly; water sitting in a pit doesn't cut down
+                let height = elevation[receiver];
+                if elevation[idx] <= height {
+                    continue;
+                }
+
+                let (x1, y1) = elevation.from_idx(idx);
+                let (x2, y2) = elevation.from_idx(receiver);
+                let dist = if x1 != x2 && y1 != y2 {
+                    std::f64::consts::SQRT_2
+                } else {
+                    1.0
+                };
+
+                let f = self.erodibility * dt * area[idx].powf(self.area_exponent) / dist;
+                elevation[idx] = ((elevation[idx] + f * height) / (1.0 + f)).max(SEA_LEVEL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ramp rising from the sea to the east, folded into a valley along y = 20
+    fn ramp() -> Elevation {
+        Elevation::from(Grid::from_fn(40, |x, y| {
+            if x == 0 {
+                -0.1
+            } else {
+                0.01 * f64::from(x) + 0.002 * f64::from(y.abs_diff(20))
+            }
+        }))
+    }
+
+    #[test]
+    fn uplift_raises_the_land() {
+        let mut elevation = ramp();
+        let before = elevation.clone();
+        let stream_power = StreamPower {
+            erodibility: 0.0,
+            ..Default::default()
+        };
+
+        stream_power.evolve(&mut elevation, &Grid::new(40, 1.0), 10);
+
+        for ((x, y), &height) in before.cells() {
+            if height <= SEA_LEVEL {
+                assert_eq!(elevation[(x, y)], height);
+            } else {
+                assert!((elevation[(x, y)] - height - 0.01).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn rivers_incise_their_channels() {
+        let mut elevation = ramp();
+        let before = elevation.clone();
+        let stream_power = StreamPower {
+            erodibility: 0.01,
+            ..Default::default()
+        };
+
+        stream_power.evolve(&mut elevation, &Grid::new(40, 0.0), 10);
+
+        // Without uplift the land only wears down, and never below our base level at the sea
+        for ((x, y), &height) in before.cells() {
+            if height <= SEA_LEVEL {
+                assert_eq!(elevation[(x, y)], height);
+            } else {
+                assert!(elevation[(x, y)] <= height);
+                assert!(elevation[(x, y)] >= SEA_LEVEL);
+            }
+        }
+
+        // The valley floor gathers the most water, and so is cut down the most
+        let cut = |x, y| before[(x, y)] - elevation[(x, y)];
+        assert!(cut(30, 20) > 0.0);
+        assert!(cut(30, 20) > cut(30, 5));
+    }
+}
@@ -0,0 +1,100 @@
+//! Simulate thermal erosion
+//!
+//! Material on slopes steeper than the talus angle breaks loose and slides down to its lower
+//! neighbors, relaxing cliffs into scree slopes. This is based on the method described in
+//! Olsen's "Realtime Procedural Terrain Generation" (2004).
+
+use super::elevation::{Elevation, HEIGHT_SCALE};
+use super::grid::Grid;
+use std::f64::consts::SQRT_2;
+
+/// Parameters for thermal erosion
+#[derive(Debug, Clone)]
+pub struct Thermal {
+    /// The steepest stable slope, in degrees
+    pub talus_angle: f64,
+    /// Fraction of the excess material above the talus angle moved in each iteration
+    pub rate: f64,
+    /// Number of droplets to simulate between thermal passes when interleaved with hydraulic
+    /// erosion
+    pub interval: u32,
+}
+
+impl Default for Thermal {
+    fn default() -> Self {
+        Self {
+            talus_angle: 35.0,
+            rate: 0.5,
+            interval: 5_000,
+        }
+    }
+}
+
+/// Run `iterations` passes of thermal erosion over the whole map
+pub fn erode(elevation: &mut Elevation, thermal: &Thermal, iterations: u32) {
+    // Convert our talus angle into the largest stable height difference between neighbors
+    let talus = thermal.talus_angle.to_radians().tan() / HEIGHT_SCALE;
+    let mut delta = Grid::new(elevation.size(), 0.0);
+
+    for _ in 0..iterations {
+        for ((x, y), &height) in elevation.cells() {
+            // Find each neighbor's excess height difference over the talus threshold
+            let mut total = 0.0;
+            let mut max: f64 = 0.0;
+            let mut lower = [((0, 0), 0.0); 8];
+            let mut count = 0;
+            for (nx, ny) in elevation.neighbors8(x, y) {
+                // Diagonal neighbors are further away, so can sustain a bigger difference
+                let dist = if nx != x && ny != y { SQRT_2 } else { 1.0 };
+                let diff = height - elevation[(nx, ny)];
+                if diff > talus * dist {
+                    lower[count] = ((nx, ny), diff);
+                    count += 1;
+                    total += diff;
+                    max = max.max(diff - talus * dist);
+                }
+            }
+            if count == 0 {
+                continue;
+            }
+
+            // Move half of the excess (which would level the steepest pair), scaled by our rate,
+            // and share it out in proportion to each neighbor's height difference
+            let moved = thermal.rate * max / 2.0;
+            delta[(x, y)] -= moved;
+            for &(neighbor, diff) in lower[..count].iter() {
+                delta[neighbor] += moved * diff / total;
+            }
+        }
+
+        for (height, delta) in elevation.iter_mut().zip(delta.iter_mut()) {
+            *height += *delta;
+            *delta = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spike_relaxes() {
+        let mut grid = Grid::new(9, 0.0);
+        grid[(4, 4)] = 1.0;
+        let mut elevation = Elevation::from(grid);
+
+        erode(&mut elevation, &Thermal::default(), 200);
+
+        // Material is conserved...
+        let total: f64 = elevation.iter().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        // ...and no slope remains much steeper than the talus angle
+        let talus = 35.0_f64.to_radians().tan() / HEIGHT_SCALE;
+        for ((x, y), &height) in elevation.cells() {
+            for neighbor in elevation.neighbors4(x, y) {
+                assert!(height - elevation[neighbor] < talus * 1.5);
+            }
+        }
+    }
+}
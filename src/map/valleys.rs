@@ -0,0 +1,197 @@
+//! Carve rivers into the terrain
+//!
+//! Each river cuts a channel below the surrounding land, deeper for larger rivers, and wears a
+//! valley around it whose banks rise smoothly back to the original terrain. Channels are kept
+//! flowing downhill all the way to the sea, cutting through any sills in their way.
+
+use super::drainage::Drainage;
+use super::elevation::Elevation;
+use super::grid::Grid;
+use super::math::smoothstep;
+use super::watershed::RiverPoint;
+use super::SEA_LEVEL;
+
+/// Lowest a channel can be cut, so that rivers don't turn into inlets of the sea
+const MIN_CHANNEL: f64 = 0.0005;
+
+/// Parameters for carving river valleys
+#[derive(Debug, Clone)]
+pub struct Valleys {
+    /// Depth of a headwater stream's channel below the surrounding terrain
+    pub depth: f64,
+    /// Extra channel depth for each Strahler order
+    pub depth_per_order: f64,
+    /// Distance, in cells, from a headwater stream to the top of its valley's banks
+    pub width: f64,
+    /// Extra valley width for each Strahler order
+    pub width_per_order: f64,
+}
+
+impl Default for Valleys {
+    fn default() -> Self {
+        Self {
+            depth: 0.004,
+            depth_per_order: 0.004,
+            width: 2.0,
+            width_per_order: 2.0,
+        }
+    }
+}
+
+impl Valleys {
+    /// Carve the given rivers into the terrain
+    pub fn carve(
+        &self,
+        elevation: &mut Elevation,
+        drainage: &Drainage,
+        rivers: &[Vec<RiverPoint>],
+    ) {
+        let size = elevation.size();
+
+        // How many orders above a headwater stream the river through each cell is, if any
+        let mut orders = Grid::new(size, None);
+        for point in rivers.iter().flatten() {
+            let idx = elevation.to_idx(point.x, point.y);
+            if elevation[idx] > SEA_LEVEL {
+                let order = u32::from(point.order).saturating_sub(1);
+                orders[idx] = Some(orders[idx].map_or(order, |o: u32| o.max(order)));
+            }
+        }
+
+        // Cut each channel below the terrain, then make sure it never climbs on its way downstream
+        let mut channel = Grid::from_fn(size, |x, y| {
+            let height = elevation[(x, y)];
+            match orders[(x, y)] {
+                // Channels are kept above the sea, but never raised above the land they cut into
+                Some(order) => (height - self.channel_depth(order))
+                    .max(SEA_LEVEL + MIN_CHANNEL)
+                    .min(height),
+                None => height,
+            }
+        });
+        self.settle(&mut channel, &orders, drainage);
+
+        // Now shape each valley's banks, blending from the channel up to the original terrain
+        let mut carved = channel.clone();
+        for ((x, y), order) in orders.cells() {
+            let order = match order {
+                Some(order) => *order,
+                None => continue,
+            };
+            let bottom = channel[(x, y)];
+            let width = self.width + self.width_per_order * f64::from(order);
+            let reach = width.ceil() as i64;
+
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (nx, ny) = (i64::from(x) + dx, i64::from(y) + dy);
+                    if !elevation.in_bounds(nx, ny) {
+                        continue;
+                    }
+                    let idx = elevation.to_idx(nx as u32, ny as u32);
+                    let dist = ((dx * dx + dy * dy) as f64).sqrt() / width;
+                    if dist >= 1.0 || elevation[idx] <= SEA_LEVEL {
+                        continue;
+                    }
+
+                    let t = smoothstep(dist);
+                    let bank = bottom + (elevation[idx] - bottom).max(0.0) * t;
+                    carved[idx] = f64::min(carved[idx], bank);
+                }
+            }
+        }
+        // Overlapping valleys may have cut into a channel; keep it running downhill
+        self.settle(&mut carved, &orders, drainage);
+
+        for (height, &new) in elevation.iter_mut().zip(carved.iter()) {
+            *height = height.min(new);
+        }
+    }
+
+    fn channel_depth(&self, order: u32) -> f64 {
+        self.depth + self.depth_per_order * f64::from(order)
+    }
+
+    /// Lower river cells so that none is higher than any cell upstream of it
+    fn settle(&self, heights: &mut Grid<f64>, orders: &Grid<Option<u32>>, drainage: &Drainage) {
+        // The stack runs downstream first, so walk it backwards to work down from the sources
+        for &idx in drainage.stack().iter().rev() {
+            if orders[idx].is_none() {
+                continue;
+            }
+            if let Some(receiver) = drainage.receiver(idx) {
+                if orders[receiver].is_some() {
+                    heights[receiver] = heights[receiver].min(heights[idx]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Map, Strahler};
+
+    #[test]
+    fn channels_run_downhill() {
+        let mut map = Map::new(5, 120);
+        let size = map.size();
+        let before: Vec<_> = (0..size * size)
+            .map(|i| map.get_elevation(i % size, i / size))
+            .collect();
+        let rivers = map.get_rivers();
+
+        map.carve_valleys(&Valleys::default());
+
+        // Carving only ever lowers the terrain...
+        for i in 0..size * size {
+            assert!(map.get_elevation(i % size, i / size) <= before[i as usize]);
+        }
+        // ...and along each river the land falls all the way to the mouth
+        for river in rivers.iter() {
+            for pair in river.windows(2) {
+                let downstream = map.get_elevation(pair[0].x, pair[0].y);
+                let upstream = map.get_elevation(pair[1].x, pair[1].y);
+                assert!(downstream <= upstream);
+            }
+        }
+    }
+
+    #[test]
+    fn low_channels_run_downhill() {
+        // A valley running west to the sea, over a sill with a hollow just upstream of it that's
+        // already lower than any channel we'd cut
+        let grid = Grid::from_fn(40, |x, y| {
+            let floor = match x {
+                0..=9 => return -0.1,
+                10 => 0.003,
+                11 => 0.0001,
+                _ => 0.001 * f64::from(x - 11),
+            };
+            floor + 0.01 * f64::from((y as i32 - 20).abs())
+        });
+        let mut elevation = Elevation::from(grid);
+        let drainage = Drainage::new(&elevation);
+        let river: Vec<_> = (10..30)
+            .map(|x| RiverPoint {
+                x,
+                y: 20,
+                order: Strahler::from(1),
+                flow: 1.0,
+            })
+            .collect();
+
+        Valleys::default().carve(&mut elevation, &drainage, &[river]);
+
+        for x in 10..29 {
+            assert!(
+                elevation[(x, 20)] <= elevation[(x + 1, 20)],
+                "river climbs from {} to {} at x = {}",
+                elevation[(x + 1, 20)],
+                elevation[(x, 20)],
+                x
+            );
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Grow vegetation over our island
+//!
+//! How densely plants grow depends on the biome, chosen by height and by moisture from the
+//! distance to the nearest river, and is thinned out on steep slopes, in the salt spray along the
+//! coast, and under snow. Plants are then scattered by Poisson disk sampling, so they're evenly
+//! spaced without falling into rows, and each is given a species suited to its biome.
+
+use super::grid::Grid;
+use super::math::{ramp, rank_moisture};
+use super::{Map, SEA_LEVEL};
+use crate::voronoi::Biome;
+use fast_poisson::Poisson2D;
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::Path;
+
+/// The kinds of plants we grow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Species {
+    Palm,
+    Broadleaf,
+    Jungle,
+    Conifer,
+    Fern,
+    Shrub,
+    Heather,
+    Grass,
+    Cactus,
+}
+
+/// A single plant, ready to be spawned
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Plant {
+    pub x: f64,
+    pub y: f64,
+    /// Height of the terrain the plant stands on
+    pub elevation: f64,
+    pub species: Species,
+    pub biome: Biome,
+    /// Size of this plant relative to others of its species
+    pub scale: f64,
+}
+
+/// Parameters for growing vegetation
+#[derive(Debug, Clone)]
+pub struct Vegetation {
+    /// Closest distance, in cells, between any two plants
+    pub spacing: f64,
+    /// Height below which land along the coast is beach
+    pub beach_height: f64,
+    /// Distance, in cells, from the coast within which salt spray thins out our plants
+    pub coast_band: f64,
+    /// Fraction of the plants that survive the salt spray right at the coast
+    pub salt_tolerance: f64,
+    /// Slope, in degrees, above which plants begin to thin out
+    pub steep_angle: f64,
+    /// Slope, in degrees, above which nothing can grow
+    pub bare_angle: f64,
+}
+
+impl Default for Vegetation {
+    fn default() -> Self {
+        Self {
+            spacing: 3.0,
+            beach_height: 0.05,
+            coast_band: 8.0,
+            salt_tolerance: 0.3,
+            steep_angle: 25.0,
+            bare_angle: 45.0,
+        }
+    }
+}
+
+impl Vegetation {
+    /// Grow vegetation over `map`, scattering plants from the given seed
+    pub fn grow(&self, map: &Map, seed: u64) -> Foliage {
+        let size = map.size();
+
+        // Moisture comes from our rivers, spreading out over the land; coast distance we'll need
+        // for our beaches and salt spray
+        let rivers = distance_from(map, map.get_rivers().iter().flatten().map(|p| (p.x, p.y)));
+        let coast = distance_from(map, map.get_coast().iter().copied());
+        let rivers: Vec<_> = rivers.iter().copied().collect();
+        let moisture = rank_moisture(&rivers, |idx| {
+            let (x, y) = map.from_idx(idx);
+            map.get_elevation(x, y) > SEA_LEVEL
+        });
+
+        let biomes = Grid::from_fn(size, |x, y| {
+            let height = map.get_elevation(x, y);
+            if height <= SEA_LEVEL {
+                Biome::Ocean
+            } else if coast[(x, y)] <= 2 && height < self.beach_height {
+                Biome::Beach
+            } else {
+                Biome::classify(height, moisture[map.to_idx(x, y)])
+            }
+        });
+
+        let density = Grid::from_fn(size, |x, y| {
+            let biome = biomes[(x, y)];
+            let (density, _) = flora(biome);
+            if density <= 0.0 {
+                return 0.0;
+            }
+
+            let angle = map.get_normal(x, y).z.abs().acos().to_degrees();
+            let slope = 1.0 - ramp(self.steep_angle, self.bare_angle, angle);
+            // Beach plants are at home in the salt spray
+            let salt = if biome == Biome::Beach {
+                1.0
+            } else {
+                let t = ramp(0.0, self.coast_band, f64::from(coast[(x, y)]));
+                self.salt_tolerance + (1.0 - self.salt_tolerance) * t
+            };
+
+            density * slope * salt * (1.0 - map.get_snow(x, y))
+        });
+
+        // Scatter candidates evenly over the map, then keep each as likely as the density there
+        let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
+        let plants = Poisson2D::new()
+            .with_dimensions([f64::from(size), f64::from(size)], self.spacing)
+            .with_seed(seed)
+            .iter()
+            .filter_map(|[x, y]| {
+                let cell = ((x as u32).min(size - 1), (y as u32).min(size - 1));
+                if rng.gen::<f64>() >= density[cell] {
+                    return None;
+                }
+
+                let biome = biomes[cell];
+                let (_, species) = flora(biome);
+                let species = species.choose_weighted(&mut rng, |s| s.1).ok()?.0;
+
+                Some(Plant {
+                    x,
+                    y,
+                    elevation: map.sample_elevation(x, y),
+                    species,
+                    biome,
+                    scale: rng.gen_range(0.75..1.25),
+                })
+            })
+            .collect();
+
+        Foliage { density, plants }
+    }
+}
+
+/// The vegetation grown over a map
+pub struct Foliage {
+    /// How densely plants grow in each cell, from 0.0 to 1.0
+    density: Grid<f64>,
+    plants: Vec<Plant>,
+}
+
+#[allow(dead_code)]
+impl Foliage {
+    /// How densely plants grow at (x, y), from 0.0 for bare ground to 1.0
+    pub fn density(&self, x: u32, y: u32) -> f64 {
+        self.density[(x, y)]
+    }
+
+    pub fn plants(&self) -> &[Plant] {
+        &self.plants
+    }
+
+    /// List our plants as CSV, one plant to a row
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("x,y,elevation,species,biome,scale\n");
+        for plant in self.plants.iter() {
+            csv.push_str(&format!(
+                "{:.2},{:.2},{:.4},{:?},{:?},{:.3}\n",
+                plant.x, plant.y, plant.elevation, plant.species, plant.biome, plant.scale
+            ));
+        }
+
+        csv
+    }
+
+    /// List our plants as a JSON array of objects
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.plants)
+    }
+
+    /// Save our plants as a CSV file
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_csv())
+    }
+
+    /// Save our plants as a JSON file
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json()?)
+    }
+}
+
+/// How densely plants grow in each biome, and the species found there with their relative weights
+fn flora(biome: Biome) -> (f64, &'static [(Species, f64)]) {
+    use Species::*;
+
+    match biome {
+        Biome::Beach => (0.3, &[(Palm, 0.7), (Grass, 0.3)]),
+        Biome::Tundra => (0.1, &[(Heather, 0.7), (Grass, 0.3)]),
+        Biome::Taiga => (0.8, &[(Conifer, 0.9), (Shrub, 0.1)]),
+        Biome::Shrubland => (0.4, &[(Shrub, 0.7), (Conifer, 0.2), (Heather, 0.1)]),
+        Biome::TemperateDesert => (0.1, &[(Shrub, 0.6), (Grass, 0.4)]),
+        Biome::TemperateRainForest => (1.0, &[(Conifer, 0.6), (Fern, 0.4)]),
+        Biome::TemperateDeciduousForest => (0.8, &[(Broadleaf, 0.8), (Shrub, 0.2)]),
+        Biome::Grassland => (0.3, &[(Grass, 0.8), (Broadleaf, 0.1), (Shrub, 0.1)]),
+        Biome::TropicalRainForest => (1.0, &[(Jungle, 0.7), (Fern, 0.2), (Palm, 0.1)]),
+        Biome::TropicalSeasonalForest => (0.7, &[(Broadleaf, 0.6), (Palm, 0.2), (Shrub, 0.2)]),
+        Biome::SubtropicalDesert => (0.05, &[(Cactus, 0.6), (Shrub, 0.4)]),
+        // Nothing grows in the water, on bare rock, or under permanent snow
+        _ => (0.0, &[]),
+    }
+}
+
+/// Distance, in cells, from each land cell to the nearest of `sources`, spreading over the land
+///
+/// Cells the land doesn't connect to any source are left at `u32::MAX`.
+fn distance_from<I: Iterator<Item = (u32, u32)>>(map: &Map, sources: I) -> Grid<u32> {
+    let mut distance = Grid::new(map.size(), u32::MAX);
+    let mut queue = VecDeque::new();
+    for source in sources {
+        distance[source] = 0;
+        queue.push_back(source);
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let next = distance[(x, y)] + 1;
+        for neighbor in distance.neighbors8(x, y).collect::<Vec<_>>() {
+            if distance[neighbor] == u32::MAX
+                && map.get_elevation(neighbor.0, neighbor.1) > SEA_LEVEL
+            {
+                distance[neighbor] = next;
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plants_grow_only_on_land() {
+        let mut map = Map::new(5, 200);
+        let foliage = map.grow_foliage(&Vegetation::default());
+
+        assert!(!foliage.plants().is_empty());
+        for plant in foliage.plants() {
+            let (x, y) = (plant.x as u32, plant.y as u32);
+            assert!(map.get_elevation(x, y) > SEA_LEVEL);
+            assert!(foliage.density(x, y) > 0.0);
+            assert!(flora(plant.biome).1.iter().any(|s| s.0 == plant.species));
+        }
+        for ((x, y), &density) in foliage.density.cells() {
+            assert!((0.0..=1.0).contains(&density));
+            if map.get_elevation(x, y) <= SEA_LEVEL {
+                assert_eq!(density, 0.0);
+            }
+        }
+
+        // One row for each plant, plus the header
+        assert_eq!(foliage.to_csv().lines().count(), foliage.plants().len() + 1);
+        let json: serde_json::Value = serde_json::from_str(&foliage.to_json().unwrap()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), foliage.plants().len());
+    }
+}
@@ -1,70 +1,274 @@
-use std::cmp::Reverse;
+//! Drainage basins and the rivers that run through them
+//!
+//! Every land cell drains to exactly one outlet on the coast, and all the cells draining to the
+//! same outlet make up that outlet's watershed. Larger watersheds gather enough water for a river
+//! to run down to their outlet.
 
 //pub mod lake;
 pub mod river;
-pub mod strahler;
+use super::drainage::Drainage;
+use super::elevation::Elevation;
+use super::grid::Grid;
+use super::strahler;
+use strahler::Strahler;
 
 use river::River;
+use std::f64::consts::SQRT_2;
 
-const WATERSHED_START: f64 = 0.8;
+/// Fraction of the map that must drain through a cell for a river to begin there
+const RIVER_AREA: f64 = 1.0 / 3000.0;
 
+/// The drainage basin of a single outlet, and its river if it has one
 #[derive(Debug)]
 pub struct Watershed {
-    river: river::River,
+    /// The cell where this watershed drains into the sea
+    outlet: usize,
+    river: Option<River>,
+    /// Number of cells draining to our outlet
+    area: f64,
+    /// Height of our highest cell above our lowest
+    relief: f64,
+    /// Length, in cells, of our river's main stem
+    length: f64,
+}
+
+/// A point along a river's course
+#[derive(Debug, Clone, Copy)]
+pub struct RiverPoint {
+    pub x: u32,
+    pub y: u32,
+    pub order: Strahler,
+    /// Number of cells draining through this point
+    pub flow: f64,
 }
 
 impl Watershed {
-    pub fn create_all(map: &super::Map) -> Vec<Watershed> {
-        let mut seeds: Vec<_> = map
-            .elevation
-            .iter()
-            .enumerate()
-            .filter(|&(_, &h)| h > WATERSHED_START)
-            .collect();
+    /// Delineate every watershed on the map
+    ///
+    /// Returns the watersheds along with the index of the watershed each cell belongs to, or
+    /// `None` for cells in the ocean.
+    pub fn create_all(
+        drainage: &Drainage,
+        elevation: &Elevation,
+    ) -> (Vec<Watershed>, Grid<Option<usize>>) {
+        let area = drainage.area();
+        let mut watersheds: Vec<Watershed> = Vec::new();
+        let mut basins = Grid::new(elevation.size(), None);
+        // The watershed draining to each outlet, if any yet
+        let mut outlets = Grid::new(elevation.size(), None);
+        // The lowest and highest cells in each watershed
+        let mut range = Vec::new();
 
-        seeds.sort_unstable_by(|a, b| {
-            // Arguments are reversed because we want a reversed sort
-            b.1.partial_cmp(a.1).unwrap()
-        });
+        // Our stack runs upstream, so each cell's receiver has found its watershed before we reach
+        // the cell itself
+        for &idx in drainage.stack() {
+            let basin = match drainage.receiver(idx).map(|r| (r, basins[r])) {
+                Some((_, Some(basin))) => basin,
+                // We drain straight into the sea (or off the edge of the map)
+                receiver => {
+                    let outlet = receiver.map_or(idx, |(r, _)| r);
+                    *outlets[outlet].get_or_insert_with(|| {
+                        watersheds.push(Watershed {
+                            outlet,
+                            river: None,
+                            area: 0.0,
+                            relief: 0.0,
+                            length: 0.0,
+                        });
+                        range.push((f64::INFINITY, f64::NEG_INFINITY));
+                        watersheds.len() - 1
+                    })
+                }
+            };
 
-        if seeds.len() > 5 {
-            seeds.resize(5, (0, &0.0));
+            basins[idx] = Some(basin);
+            watersheds[basin].area += 1.0;
+            let (low, high) = &mut range[basin];
+            *low = f64::min(*low, elevation[idx]);
+            *high = f64::max(*high, elevation[idx]);
         }
-
-        let mut rivers = Vec::new();
-        while let Some(start) = seeds.pop() {
-            let (idx, _) = start;
-            rivers.push(River::new_from(map, idx));
+        for (watershed, (low, high)) in watersheds.iter_mut().zip(range) {
+            watershed.relief = high - low;
         }
 
-        // Sort rivers so that we start with the longest
-        rivers.sort_unstable_by_key(|river| Reverse(river.len()));
-
-        // Merge rivers
-        let mut merged = Vec::new();
-        for river in rivers {
-            match merged
-                .iter_mut()
-                .find(|r: &&mut River| r.mouth() == river.mouth())
-            {
-                Some(parent_river) => parent_river.merge(river),
-                None => merged.push(river),
+        // Now find the rivers, each running down to its watershed's outlet
+        let threshold = RIVER_AREA * area.len() as f64;
+        for river in River::create_all(drainage, |idx| area[idx] >= threshold) {
+            if let Some(basin) = outlets[river.mouth().idx] {
+                let watershed = &mut watersheds[basin];
+                watershed.length = river
+                    .main_stem(0)
+                    .map(|id| river.node(id).idx)
+                    .collect::<Vec<_>>()
+                    .windows(2)
+                    .map(|pair| {
+                        let (a, b) = (elevation.from_idx(pair[0]), elevation.from_idx(pair[1]));
+                        if a.0 != b.0 && a.1 != b.1 {
+                            SQRT_2
+                        } else {
+                            1.0
+                        }
+                    })
+                    .sum();
+                watershed.river = Some(river);
             }
         }
 
-        merged
-            .into_iter()
-            .filter_map(|mut river| {
-                if river.prune() {
-                    Some(Watershed { river })
-                } else {
-                    None
-                }
+        (watersheds, basins)
+    }
+
+    /// The cell where this watershed drains into the sea
+    #[allow(dead_code)]
+    pub fn outlet(&self) -> usize {
+        self.outlet
+    }
+
+    /// This watershed's river network, if it's large enough to have one
+    #[allow(dead_code)]
+    pub fn river(&self) -> Option<&River> {
+        self.river.as_ref()
+    }
+
+    /// Number of cells draining to this watershed's outlet
+    #[allow(dead_code)]
+    pub fn area(&self) -> f64 {
+        self.area
+    }
+
+    /// Height of this watershed's highest cell above its lowest
+    #[allow(dead_code)]
+    pub fn relief(&self) -> f64 {
+        self.relief
+    }
+
+    /// Length, in cells, of this watershed's river from its mouth to its farthest source, or 0.0
+    /// if it has no river
+    #[allow(dead_code)]
+    pub fn main_stem_length(&self) -> f64 {
+        self.length
+    }
+
+    /// The courses of this watershed's river and its tributaries, each running upstream from
+    /// where it meets the sea or its parent river
+    pub fn river_paths(&self, map: &super::Map, drainage: &Drainage) -> Vec<Vec<RiverPoint>> {
+        self.river
+            .iter()
+            .flat_map(River::paths)
+            .map(|path| {
+                path.into_iter()
+                    .map(|(idx, order)| {
+                        let (x, y) = map.from_idx(idx);
+                        RiverPoint {
+                            x,
+                            y,
+                            order,
+                            flow: drainage.area()[idx],
+                        }
+                    })
+                    .collect()
             })
             .collect()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{Map, Strahler, SEA_LEVEL};
 
-    pub fn river_segments(&self) -> Vec<(usize, usize)> {
-        self.river.segments()
+    #[test]
+    fn rivers_are_connected() {
+        let map = Map::new(3, 100);
+        let rivers = map.get_rivers();
+        assert!(!rivers.is_empty());
+
+        for river in rivers.iter() {
+            // Each course runs from cell to neighboring cell...
+            for pair in river.windows(2) {
+                let dx = (i64::from(pair[0].x) - i64::from(pair[1].x)).abs();
+                let dy = (i64::from(pair[0].y) - i64::from(pair[1].y)).abs();
+                assert!(dx <= 1 && dy <= 1);
+            }
+
+            // ...and begins in the sea or on another river
+            let mouth = river[0];
+            let joins = rivers
+                .iter()
+                .flatten()
+                .filter(|p| (p.x, p.y) == (mouth.x, mouth.y))
+                .count();
+            assert!(map.get_elevation(mouth.x, mouth.y) <= SEA_LEVEL || joins > 1);
+        }
+    }
+
+    #[test]
+    fn river_trees_are_exact() {
+        let map = Map::new(3, 100);
+
+        for river in map.get_watersheds().iter().filter_map(|w| w.river()) {
+            assert_eq!(river.mouth().downstream, None);
+
+            for (id, node) in river.nodes().iter().enumerate() {
+                // Every node drains to the mouth, and its neighbors agree on how they're linked
+                assert_eq!(river.downstream(id).last(), Some(0));
+                for &up in node.upstream.iter() {
+                    assert_eq!(river.node(up).downstream, Some(id));
+                }
+
+                let inflows = node.upstream.iter().map(|&up| river.node(up));
+                assert_eq!(
+                    node.strahler,
+                    Strahler::confluence(inflows.clone().map(|up| up.strahler))
+                );
+                assert_eq!(node.shreve, inflows.map(|up| up.shreve).sum::<u32>().max(1));
+            }
+
+            // The main stem and its tributaries together reach every node
+            let mut reached = river.main_stem(0).count();
+            for id in river.main_stem(0) {
+                reached += river
+                    .tributaries(id)
+                    .map(|t| river.upstream(t).count())
+                    .sum::<usize>();
+            }
+            assert_eq!(reached, river.len());
+            assert_eq!(river.upstream(0).count(), river.len());
+        }
+    }
+
+    #[test]
+    fn basins_cover_the_land() {
+        let map = Map::new(5, 100);
+        let watersheds = map.get_watersheds();
+
+        let mut areas = vec![0.0; watersheds.len()];
+        for y in 0..map.size() {
+            for x in 0..map.size() {
+                let basin = map.get_basin(x, y);
+                let idx = map.elevation.to_idx(x, y);
+                assert_eq!(
+                    basin.is_some(),
+                    map.rivers().drainage.stack().contains(&idx)
+                );
+
+                // Following the water downhill from any land cell leads to its watershed's outlet
+                if let Some(basin) = basin {
+                    areas[basin] += 1.0;
+                    let outlet =
+                        std::iter::successors(Some(idx), |&i| map.rivers().drainage.receiver(i))
+                            .last()
+                            .unwrap();
+                    assert_eq!(outlet, watersheds[basin].outlet());
+                }
+            }
+        }
+
+        for (watershed, area) in watersheds.iter().zip(areas) {
+            assert_eq!(watershed.area(), area);
+            assert!(watershed.relief() >= 0.0);
+            if let Some(river) = watershed.river() {
+                assert_eq!(river.mouth().idx, watershed.outlet());
+                assert!(watershed.main_stem_length() >= river.main_stem(0).count() as f64 - 1.0);
+            }
+        }
     }
 }
@@ -1,201 +1,221 @@
 use super::strahler::Strahler;
+use crate::map::drainage::Drainage;
 
+/// Identifies a node within its river
+pub type NodeId = usize;
+
+/// A single cell along a river's course
+#[derive(Debug)]
+pub struct Node {
+    /// Index of this node's cell in the map
+    pub idx: usize,
+    /// The node this one flows into, or `None` at the river's mouth
+    pub downstream: Option<NodeId>,
+    /// The nodes flowing into this one; the main stem continues through the first of them
+    pub upstream: Vec<NodeId>,
+    /// Strahler order of the river through this node
+    pub strahler: Strahler,
+    /// Shreve magnitude of the river through this node: the number of sources upstream of it
+    pub shreve: u32,
+}
+
+/// A river and all of its tributaries, as a tree of nodes rooted at its mouth
 #[derive(Debug)]
 pub struct River {
-    river: Vec<usize>,
-    order: Vec<Strahler>,
-    branches: Vec<(usize, Self)>,
+    /// Our nodes, beginning with the mouth; every node comes after the node it flows into
+    nodes: Vec<Node>,
 }
 
+#[allow(dead_code)]
 impl River {
-    // pub fn new() -> Self {
-    //     Self {
-    //         river: Vec::new(),
-    //         order: Vec::new(),
-    //         branches: Vec::new(),
-    //     }
-    // }
-
-    pub fn new_from(map: &crate::map::Map, start_idx: usize) -> Self {
-        let mut river = vec![start_idx];
-
-        let mut current = start_idx;
-        loop {
-            // Find the lowest neighbor
-            let (x, y) = map.from_idx(current);
-            if let Some(lowest) = map
-                .get_neighbors(x, y)
-                .into_iter()
-                .filter_map(|(x, y)| {
-                    let idx = map.to_idx(x, y);
-                    if river.contains(&idx) {
-                        None
-                    } else {
-                        Some(idx)
-                    }
-                })
-                .min_by(|&idx1, &idx2| {
-                    if map.elevation[idx1] < map.elevation[idx2] {
-                        std::cmp::Ordering::Less
-                    } else {
-                        std::cmp::Ordering::Greater
-                    }
-                })
-            {
-                // // Make sure it's lower than us
-                // if map.heightmap[lowest] > map.heightmap[current] {
-                //     // TODO: Spawn a lake here
-                //     break;
-                // } else {
-                //     current = lowest;
-                // }
-                current = lowest;
-
-                // Check if we've reached water
-                if map.elevation[current] <= crate::SEA_LEVEL {
-                    break;
+    /// Begin a new river at the cell `mouth`
+    fn new(mouth: usize) -> Self {
+        Self {
+            nodes: vec![Node {
+                idx: mouth,
+                downstream: None,
+                upstream: Vec::new(),
+                strahler: Strahler::default(),
+                shreve: 0,
+            }],
+        }
+    }
+
+    /// Build every river network on the map from the drainage tree
+    ///
+    /// Rivers run through each cell for which `is_river` is true, and each ends at the first cell
+    /// downstream that isn't a river, usually in the sea.
+    pub fn create_all<F: Fn(usize) -> bool>(drainage: &Drainage, is_river: F) -> Vec<River> {
+        let area = drainage.area();
+        let mut rivers: Vec<River> = Vec::new();
+        // The river and node at each cell, if any
+        let mut nodes: Vec<Option<(usize, NodeId)>> = vec![None; area.len()];
+
+        // Our stack runs upstream, so each cell's receiver already has its node by the time we
+        // reach it
+        for &idx in drainage.stack().iter().filter(|&&idx| is_river(idx)) {
+            let receiver = drainage.receiver(idx).unwrap_or(idx);
+            let (river, downstream) = match nodes[receiver] {
+                Some(node) => node,
+                None => {
+                    rivers.push(River::new(receiver));
+                    let node = (rivers.len() - 1, 0);
+                    nodes[receiver] = Some(node);
+                    node
                 }
+            };
 
-                river.push(current);
-            } else {
-                break;
+            if receiver != idx {
+                let id = rivers[river].push(downstream, idx);
+                nodes[idx] = Some((river, id));
             }
         }
 
-        // River is in source-to-mouth order, reverse for mouth-to-source
-        river.reverse();
+        for river in rivers.iter_mut() {
+            river.update_orders(|idx| area[idx]);
+        }
 
-        // Convert this Vec into a River
-        river.into()
+        rivers
     }
 
-    pub fn merge(&mut self, other: River) {
-        self.add_branch(other.river);
+    /// Add a node at the cell `idx`, flowing into `downstream`
+    fn push(&mut self, downstream: NodeId, idx: usize) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            idx,
+            downstream: Some(downstream),
+            upstream: Vec::new(),
+            strahler: Strahler::default(),
+            shreve: 0,
+        });
+        self.nodes[downstream].upstream.push(id);
+
+        id
     }
 
-    pub fn add_branch(&mut self, branch: Vec<usize>) {
-        let (downstream, mut upstream): (Vec<usize>, Vec<usize>) =
-            branch.into_iter().partition(|i| self.river.contains(i));
-
-        if upstream.len() == 0 || downstream.len() == self.river.len() {
-            self.river.append(&mut upstream);
-            self.order.resize_with(self.river.len(), Default::default);
-
-            return;
-        }
-
-        let split_idx = downstream.len() - 1;
-
-        for (idx, branch) in self.branches.iter_mut() {
-            if *idx == split_idx && upstream[0] == branch.river[0] {
-                branch.add_branch(upstream);
-                self.update_order(split_idx);
-
-                return;
-            }
+    /// Find the Strahler and Shreve orders of every node, and put each main stem first
+    ///
+    /// The main stem follows whichever inflow drains the greatest `area`.
+    fn update_orders<F: Fn(usize) -> f64>(&mut self, area: F) {
+        // Every node comes after its downstream node, so in reverse we see all inflows first
+        for id in (0..self.nodes.len()).rev() {
+            let mut upstream = std::mem::take(&mut self.nodes[id].upstream);
+            upstream.sort_by(|&a, &b| {
+                area(self.nodes[b].idx)
+                    .partial_cmp(&area(self.nodes[a].idx))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+            let strahler = Strahler::confluence(upstream.iter().map(|&up| self.nodes[up].strahler));
+            let shreve = upstream
+                .iter()
+                .map(|&up| self.nodes[up].shreve)
+                .sum::<u32>()
+                .max(1);
+
+            let node = &mut self.nodes[id];
+            node.upstream = upstream;
+            node.strahler = strahler;
+            node.shreve = shreve;
         }
-
-        self.branches.push((split_idx, Self::from(upstream)));
-
-        self.update_order(split_idx);
     }
 
-    pub fn prune(&mut self) -> bool {
-        if self.order() < Strahler::from(1) {
-            return false;
-        }
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
 
-        // Prune all 0th-order river segments
-        self.order.retain(|&s| s > Strahler::from(0));
-        self.river.truncate(self.order.len());
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
 
-        // If we're too short now, there's no reason to exist anymore
-        if self.river.len() < 3 {
-            return false;
-        }
+    /// The river's mouth, where it meets the sea
+    pub fn mouth(&self) -> &Node {
+        &self.nodes[0]
+    }
 
-        // Now clean up our branches
-        self.branches
-            .retain(|(_, branch)| branch.order() > Strahler::from(0));
-        for (_, branch) in self.branches.iter_mut() {
-            branch.prune();
-        }
+    /// Every node from `id` down to the mouth
+    pub fn downstream(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(Some(id), move |&id| self.nodes[id].downstream)
+    }
 
-        true
+    /// Every node that drains through `id`, including `id` itself
+    pub fn upstream(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack = vec![id];
+        std::iter::from_fn(move || {
+            let id = stack.pop()?;
+            stack.extend(self.nodes[id].upstream.iter().copied());
+            Some(id)
+        })
     }
 
-    pub fn segments(&self) -> Vec<(usize, usize)> {
-        let mut segments = Vec::new();
-        let mut prev = self.river[0];
-        for &cur in self.river.iter().skip(1) {
-            segments.push((prev, cur));
+    /// The main stem from `id` up to its source, following the largest inflow at each confluence
+    pub fn main_stem(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(Some(id), move |&id| {
+            self.nodes[id].upstream.first().copied()
+        })
+    }
 
-            prev = cur;
-        }
+    /// The first nodes of the tributaries that join the main stem at `id`
+    pub fn tributaries(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes[id].upstream.iter().skip(1).copied()
+    }
 
-        for (idx, branch) in self.branches.iter() {
-            segments.push((self.river[*idx], branch.river[0]));
+    /// The nodes along this river's main stem and along each of its tributaries, each starting
+    /// from its mouth and running upstream; the river's own main stem comes first
+    ///
+    /// A tributary's stem begins at the node where it joins its parent, so the stems connect.
+    pub fn stems(&self) -> Vec<Vec<NodeId>> {
+        let mut stems = Vec::new();
+        let mut starts = vec![(None, 0)];
+
+        while let Some((junction, start)) = starts.pop() {
+            let mut stem: Vec<_> = junction.into_iter().collect();
+            for id in self.main_stem(start) {
+                stem.push(id);
+                starts.extend(self.tributaries(id).map(|tributary| (Some(id), tributary)));
+            }
 
-            segments.append(&mut branch.segments());
+            stems.push(stem);
         }
 
-        segments
+        stems
     }
 
-    pub fn len(&self) -> usize {
-        self.river.len()
-    }
+    /// The courses of this river and each of its tributaries, as in `stems`, as cells along with
+    /// the Strahler order at each
+    pub fn paths(&self) -> Vec<Vec<(usize, Strahler)>> {
+        self.stems()
+            .into_iter()
+            .enumerate()
+            .map(|(i, stem)| {
+                let mut path: Vec<_> = stem
+                    .iter()
+                    .map(|&id| (self.nodes[id].idx, self.nodes[id].strahler))
+                    .collect();
+                // Every stem after our main stem is a tributary, which keeps its own order at the
+                // junction where it joins its parent
+                if i > 0 {
+                    path[0].1 = path[1].1;
+                }
 
-    pub fn mouth(&self) -> usize {
-        self.river[0]
+                path
+            })
+            .collect()
     }
 
-    pub fn order(&self) -> Strahler {
-        self.order[0]
+    /// Each stretch of river between neighboring cells, as (downstream, upstream) cell pairs
+    pub fn segments(&self) -> Vec<(usize, usize)> {
+        self.nodes
+            .iter()
+            .filter_map(|node| Some((self.nodes[node.downstream?].idx, node.idx)))
+            .collect()
     }
 
-    fn update_order(&mut self, to_idx: usize) {
-        let mut upstream = *self.order.get(to_idx + 1).unwrap_or(&Strahler::from(0));
-
-        for (idx, order) in self.order.iter_mut().enumerate().take(to_idx + 1).rev() {
-            let mut orders: Vec<_> = self
-                .branches
-                .iter()
-                .filter_map(|(split_idx, branch)| {
-                    if *split_idx == idx {
-                        Some(branch.order())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            if !orders.is_empty() {
-                orders.push(upstream);
-
-                // Sort our Strahler numbers in ascending order, then sum them.
-                // This will let e.g. two 1st-order rivers joining a 2nd-order become 3rd-order
-                // Because in Strahler terms, 1+1=2 and 2+2=3, but 2+1=2
-                // Technically not accurate per the definition of Strahler number, which holds that
-                // order i can only be achieved when two or more children are i-1
-                orders.sort();
-
-                upstream = orders.into_iter().reduce(std::ops::Add::add).unwrap();
-                // We know it's not empty so this will never panic
-            }
-
-            *order = upstream;
-        }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
     }
-}
-
-impl From<Vec<usize>> for River {
-    fn from(river: Vec<usize>) -> River {
-        let order = vec![Default::default(); river.len()];
 
-        Self {
-            river,
-            order,
-            branches: Vec::new(),
-        }
+    pub fn order(&self) -> Strahler {
+        self.mouth().strahler
     }
 }
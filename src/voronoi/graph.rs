@@ -0,0 +1,323 @@
+//! Navigate the Voronoi map as a graph of regions, corners, and edges
+//!
+//! Each region is a Voronoi cell, each corner is a vertex of the cells (the circumcenter of a
+//! Delaunay triangle), and each edge separates two regions and joins two corners. These handles
+//! hide the half-edge arithmetic needed to move between them, so that rivers, roads, borders, and
+//! the like can simply walk the graph.
+
+use super::{Cell, Voronoi};
+use crate::map::Strahler;
+use delaunator::{Point, EMPTY};
+
+/// A Voronoi cell
+#[derive(Clone, Copy)]
+pub struct Region<'a> {
+    voronoi: &'a Voronoi,
+    id: usize,
+}
+
+/// A vertex of the Voronoi cells, where three of them meet
+#[derive(Clone, Copy)]
+pub struct Corner<'a> {
+    voronoi: &'a Voronoi,
+    id: usize,
+}
+
+/// The boundary between two regions, running between two corners
+#[derive(Clone, Copy)]
+pub struct Edge<'a> {
+    voronoi: &'a Voronoi,
+    /// The half-edge running from the first of our regions to the second
+    id: usize,
+}
+
+#[allow(dead_code)]
+impl<'a> Region<'a> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn cell(&self) -> &'a Cell {
+        &self.voronoi.cells[self.id]
+    }
+
+    /// The seed point this region was built around
+    pub fn center(&self) -> Point {
+        self.cell().as_point()
+    }
+
+    pub fn elevation(&self) -> f64 {
+        self.cell().height
+    }
+
+    /// This region's outline, clipped to the bounds of the map
+    pub fn polygon(&self) -> Vec<Point> {
+        self.voronoi.cell_polygon(self.id)
+    }
+
+    /// The corners around this region, in counter-clockwise order
+    pub fn corners(&self) -> impl Iterator<Item = Corner<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi
+            .corners_of_point(self.id)
+            .into_iter()
+            .map(move |id| Corner { voronoi, id })
+    }
+
+    /// The edges around this region
+    ///
+    /// Edges along the border of the map only have this region on one side.
+    pub fn edges(&self) -> impl Iterator<Item = Edge<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi
+            .incoming_edges(self.id)
+            .into_iter()
+            .map(move |e| Edge::new(voronoi, e))
+    }
+
+    /// The regions sharing an edge with this one
+    pub fn neighbors(&self) -> impl Iterator<Item = Region<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi
+            .neighbors_of_point(self.id)
+            .into_iter()
+            .map(move |id| Region { voronoi, id })
+    }
+}
+
+#[allow(dead_code)]
+impl<'a> Corner<'a> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn position(&self) -> Point {
+        self.voronoi.triangle_center(self.id)
+    }
+
+    pub fn elevation(&self) -> f64 {
+        self.voronoi.corner_heights[self.id]
+    }
+
+    /// The regions meeting at this corner; corners along the border of the map touch fewer than 3
+    pub fn regions(&self) -> impl Iterator<Item = Region<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi
+            .points_of_triangle(self.id)
+            .into_iter()
+            .filter(move |&id| !voronoi.is_ghost(id))
+            .map(move |id| Region { voronoi, id })
+    }
+
+    /// The corners joined to this one by an edge
+    pub fn adjacent(&self) -> impl Iterator<Item = Corner<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi
+            .adjacent_triangles(self.id)
+            .into_iter()
+            .map(move |id| Corner { voronoi, id })
+    }
+
+    /// The edges leading away from this corner
+    pub fn edges(&self) -> impl Iterator<Item = Edge<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi
+            .edges_of_triangle(self.id)
+            .iter()
+            .filter(move |&&e| voronoi.delaunay.halfedges[e] != EMPTY)
+            .map(move |&e| Edge::new(voronoi, e))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// The corner water flows on to from here, if we're not already in the sea
+    pub fn downslope(&self) -> Option<Corner<'a>> {
+        let voronoi = self.voronoi;
+
+        voronoi.downslope[self.id].map(|id| Corner { voronoi, id })
+    }
+
+    /// Strahler order of the river through this corner, or 0 if there's no river here
+    pub fn river(&self) -> Strahler {
+        self.voronoi.strahler[self.id]
+    }
+}
+
+#[allow(dead_code)]
+impl<'a> Edge<'a> {
+    /// Wrap the given half-edge, picking the same half-edge for both directions of an edge
+    fn new(voronoi: &'a Voronoi, halfedge: usize) -> Self {
+        let opposite = voronoi.delaunay.halfedges[halfedge];
+        let origin = voronoi.delaunay.triangles[halfedge];
+
+        // Prefer the half-edge starting in a real region, then the lower of the two
+        let id = if opposite == EMPTY {
+            halfedge
+        } else if voronoi.is_ghost(origin) {
+            opposite
+        } else if voronoi.is_ghost(voronoi.delaunay.triangles[opposite]) {
+            halfedge
+        } else {
+            halfedge.min(opposite)
+        };
+
+        Self { voronoi, id }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The regions on either side of this edge
+    ///
+    /// Along the border of the map there's only one region, so the second may be `None`.
+    pub fn regions(&self) -> (Region<'a>, Option<Region<'a>>) {
+        let voronoi = self.voronoi;
+        let a = voronoi.delaunay.triangles[self.id];
+        let b = voronoi.delaunay.triangles[voronoi.next_halfedge(self.id)];
+
+        (
+            Region { voronoi, id: a },
+            Some(b)
+                .filter(|&b| !voronoi.is_ghost(b))
+                .map(|id| Region { voronoi, id }),
+        )
+    }
+
+    /// The corners at either end of this edge
+    pub fn corners(&self) -> (Corner<'a>, Corner<'a>) {
+        let voronoi = self.voronoi;
+        let opposite = voronoi.delaunay.halfedges[self.id];
+
+        (
+            Corner {
+                voronoi,
+                id: voronoi.triangle_of_edge(self.id),
+            },
+            Corner {
+                voronoi,
+                id: voronoi.triangle_of_edge(opposite),
+            },
+        )
+    }
+
+    /// Strahler order of any river running along this edge, or 0 if there isn't one
+    pub fn river(&self) -> Strahler {
+        let (a, b) = self.corners();
+        let voronoi = self.voronoi;
+
+        if voronoi.downslope[a.id] == Some(b.id) {
+            a.river()
+        } else if voronoi.downslope[b.id] == Some(a.id) {
+            b.river()
+        } else {
+            Strahler::default()
+        }
+    }
+}
+
+impl PartialEq for Region<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.voronoi, other.voronoi) && self.id == other.id
+    }
+}
+
+impl PartialEq for Corner<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.voronoi, other.voronoi) && self.id == other.id
+    }
+}
+
+impl PartialEq for Edge<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.voronoi, other.voronoi) && self.id == other.id
+    }
+}
+
+#[allow(dead_code)]
+impl Voronoi {
+    pub fn region(&self, id: usize) -> Region<'_> {
+        Region { voronoi: self, id }
+    }
+
+    pub fn corner(&self, id: usize) -> Corner<'_> {
+        Corner { voronoi: self, id }
+    }
+
+    /// Every region of the map
+    pub fn regions(&self) -> impl Iterator<Item = Region<'_>> {
+        (0..self.cells.len()).map(move |id| self.region(id))
+    }
+
+    /// Every corner of the map, including those outside it along its border
+    pub fn corners(&self) -> impl Iterator<Item = Corner<'_>> {
+        (0..self.delaunay.len()).map(move |id| self.corner(id))
+    }
+
+    /// Every edge bordering at least one region of the map
+    pub fn edges(&self) -> impl Iterator<Item = Edge<'_>> {
+        (0..self.delaunay.triangles.len())
+            .filter(move |&e| {
+                let opposite = self.delaunay.halfedges[e];
+                opposite != EMPTY && !self.is_ghost(self.delaunay.triangles[e])
+            })
+            .map(move |e| (e, Edge::new(self, e)))
+            // Each edge has two half-edges, but we only want to visit it once
+            .filter(|(e, edge)| edge.id == *e)
+            .map(|(_, edge)| edge)
+    }
+
+    /// The half-edges leading into the given point
+    fn incoming_edges(&self, point: usize) -> Vec<usize> {
+        if self.cells[point].halfedge == usize::MAX {
+            return Vec::new();
+        }
+
+        self.edges_around_point(self.cells[point].halfedge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_is_consistent() {
+        let voronoi = Voronoi::new(4, 60, 60);
+
+        let mut edges = 0;
+        for edge in voronoi.edges() {
+            edges += 1;
+            let (a, b) = edge.regions();
+            let (c1, c2) = edge.corners();
+
+            // Both ends of an edge are corners of the regions on either side of it...
+            for region in std::iter::once(a).chain(b) {
+                let corners: Vec<_> = region.corners().collect();
+                assert!(corners.contains(&c1) && corners.contains(&c2));
+                assert!(region.edges().any(|e| e == edge));
+            }
+            // ...and both regions meet at each of those corners
+            assert!(c1.regions().any(|r| r == a));
+            assert!(c1.adjacent().any(|c| c == c2));
+            if let Some(b) = b {
+                assert!(a.neighbors().any(|n| n == b));
+                assert!(c2.regions().any(|r| r == b));
+            }
+        }
+
+        // Every edge is found exactly once
+        let around: usize = voronoi.regions().map(|r| r.edges().count()).sum();
+        let shared = voronoi
+            .edges()
+            .filter(|edge| edge.regions().1.is_some())
+            .count();
+        assert_eq!(around, edges + shared);
+    }
+}
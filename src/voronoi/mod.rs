@@ -1,22 +1,104 @@
 use delaunator::{Point, Triangulation};
 
-use fast_poisson::Poisson2D;
-use super::river::River;
+use crate::map::{rank_moisture, Map, Queued, Strahler, SEA_LEVEL};
+use serde::Serialize;
+use std::collections::{BinaryHeap, VecDeque};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+mod graph;
+mod river;
+mod sampling;
+mod svg;
+#[allow(unused_imports)]
+pub use graph::{Corner, Edge, Region};
+pub use river::River;
+pub use sampling::{Density, Sampling};
+pub use svg::Layers;
+
+/// Number of land corners that must drain through a corner for it to carry a river
+const RIVER_FLOW: f64 = 40.0;
+
+/// Depth of standing water (from `Map::simulate_water`) at which a cell becomes a lake
+const LAKE_DEPTH: f64 = 0.002;
+
+/// Coastal land lower than this becomes beach
+const BEACH_HEIGHT: f64 = 0.05;
+
+/// Biomes of our polygon map
+///
+/// Land biomes follow the Whittaker-style diagram of Amit Patel's polygon map generator, chosen
+/// by elevation and moisture: <http://www-cs-students.stanford.edu/~amitp/game-programming/polygon-map-generation/>
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum Biome {
+    /// The open ocean
     Ocean,
+    /// Ocean bordering the land
     Coast,
+    /// Sea water below sea level, cut off from the ocean
     Lagoon,
+    /// Fresh water standing on the land
     Lake,
+    /// Low-lying land along the ocean
     Beach,
+    Snow,
+    Tundra,
+    Bare,
+    Scorched,
+    Taiga,
+    Shrubland,
+    TemperateDesert,
+    TemperateRainForest,
+    TemperateDeciduousForest,
+    Grassland,
+    TropicalRainForest,
+    TropicalSeasonalForest,
+    SubtropicalDesert,
 }
 
+#[allow(dead_code)]
 impl Biome {
     pub fn is_water(&self) -> bool {
-        match self {
-            Self::Ocean | Self::Coast | Self::Lagoon | Self::Lake => true,
-            _ => false,
+        matches!(self, Self::Ocean | Self::Coast | Self::Lagoon | Self::Lake)
+    }
+
+    /// Choose the land biome for the given elevation and moisture, both within [0.0, 1.0]
+    pub fn classify(height: f64, moisture: f64) -> Self {
+        if height > 0.8 {
+            if moisture > 0.5 {
+                Self::Snow
+            } else if moisture > 0.33 {
+                Self::Tundra
+            } else if moisture > 0.16 {
+                Self::Bare
+            } else {
+                Self::Scorched
+            }
+        } else if height > 0.6 {
+            if moisture > 0.66 {
+                Self::Taiga
+            } else if moisture > 0.33 {
+                Self::Shrubland
+            } else {
+                Self::TemperateDesert
+            }
+        } else if height > 0.3 {
+            if moisture > 0.83 {
+                Self::TemperateRainForest
+            } else if moisture > 0.5 {
+                Self::TemperateDeciduousForest
+            } else if moisture > 0.16 {
+                Self::Grassland
+            } else {
+                Self::TemperateDesert
+            }
+        } else if moisture > 0.66 {
+            Self::TropicalRainForest
+        } else if moisture > 0.33 {
+            Self::TropicalSeasonalForest
+        } else if moisture > 0.16 {
+            Self::Grassland
+        } else {
+            Self::SubtropicalDesert
         }
     }
 }
@@ -25,6 +107,8 @@ pub struct Cell {
     pub x: f64,
     pub y: f64,
     pub height: f64,
+    /// Moisture from nearby fresh water, within [0.0, 1.0]
+    pub moisture: f64,
     pub biome: Biome,
     halfedge: usize,
 }
@@ -44,6 +128,7 @@ impl From<Point> for Cell {
             x: point.x,
             y: point.y,
             height: 0.0,
+            moisture: 0.0,
             biome: Biome::Ocean,
             halfedge: usize::MAX,
         }
@@ -54,6 +139,7 @@ impl From<Point> for Cell {
 ///
 /// The implementation of the Voronoi graph from its Delaunay triangulation is based on the article
 /// and code at <https://mapbox.github.io/delaunator/>
+#[allow(dead_code)]
 pub struct Voronoi {
     /// Width of the map
     pub width: u32,
@@ -61,12 +147,24 @@ pub struct Voronoi {
     pub height: u32,
     /// The Voronoi polygon cells
     pub cells: Vec<Cell>,
+    /// Points outside the map that close off the cells along its edges; in the triangulation
+    /// these follow on from our cells, i.e. ghost `i` is point `cells.len() + i`
+    ghosts: Vec<Point>,
     /// The Delaunay triangulation of the Voronoi map
     pub delaunay: Triangulation,
-    /// Rivers as a series of point indexes
+    /// Height of each corner, i.e. of the circumcenter of each Delaunay triangle
+    pub corner_heights: Vec<f64>,
+    /// The corner each corner drains into, or `None` for corners in the water
+    pub downslope: Vec<Option<usize>>,
+    /// Number of land corners draining through each corner, including itself
+    pub flow: Vec<f64>,
+    /// Strahler order of the river through each corner, or 0 where there's no river
+    pub strahler: Vec<Strahler>,
+    /// Rivers as a series of corner indexes
     pub rivers: Vec<River>,
 }
 
+#[allow(dead_code)]
 impl Voronoi {
     /// Return a new Voronoi map.
     ///
@@ -76,43 +174,324 @@ impl Voronoi {
     /// * `width` - The width of the map
     /// * `height` - The height of the map
     pub fn new(seed: u64, width: u32, height: u32) -> Voronoi {
+        Self::with_sampling(seed, width, height, &Sampling::default())
+    }
+
+    /// Return a new Voronoi map, with the seeds of its cells placed as `sampling` describes.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The RNG seed for generating Voronoi "seeds", i.e. the points around which Voronoi cells are built
+    /// * `width` - The width of the map
+    /// * `height` - The height of the map
+    /// * `sampling` - How far apart to place our seeds, and how to relax them
+    pub fn with_sampling(seed: u64, width: u32, height: u32, sampling: &Sampling) -> Voronoi {
+        let density = match sampling.density {
+            Density::Custom(density) => Some(density),
+            Density::Uniform | Density::Terrain => None,
+        };
+
+        Self::build(seed, width, height, sampling, density)
+    }
+
+    fn build(
+        seed: u64,
+        width: u32,
+        height: u32,
+        sampling: &Sampling,
+        density: Option<&dyn Fn(f64, f64) -> f64>,
+    ) -> Voronoi {
         // Generate the seeds from the Poisson disk
-        // TODO: The radius should be a parameter exposed to consumers of Voronoi
-        let points: Vec<Point> = Poisson2D::new()
-            .with_dimensions([f64::from(width), f64::from(height)], 5.0)
-            .with_seed(seed)
-            .iter()
-            .map(|[x, y]| Point { x, y })
-            .collect();
+        let mut points = sampling::points(seed, width, height, sampling, density);
+
+        // Surround our map with a ring of ghost points, so that every real point is enclosed by
+        // triangles and none of them lie on the convex hull. No point on the map is more than
+        // twice our Poisson disk radius from a candidate seed, and thinning keeps every candidate
+        // within `max_radius` of a seed, so that's as far as our ghosts must stay from the map.
+        let (spacing, reach) = match density {
+            Some(_) => {
+                let max_radius = sampling.radius.max(sampling.max_radius);
+                (max_radius, max_radius + 2.0 * sampling.radius)
+            }
+            None => (sampling.radius, 2.0 * sampling.radius),
+        };
+        let ghosts = ghost_points(width, height, spacing, reach);
+
+        let mut voronoi = Self::triangulate(width, height, points, ghosts);
+        for _ in 0..sampling.relaxation {
+            // Lloyd relaxation: move each seed to the centroid of its cell, then start over
+            points = (0..voronoi.cells.len())
+                .map(|point| centroid(&voronoi.cell_polygon(point)))
+                .collect();
+            voronoi = Self::triangulate(width, height, points, voronoi.ghosts);
+        }
+
+        voronoi
+    }
+
+    fn triangulate(width: u32, height: u32, points: Vec<Point>, ghosts: Vec<Point>) -> Voronoi {
+        let mut all_points = points.clone();
+        all_points.extend(ghosts.iter().cloned());
 
-        let delaunay = delaunator::triangulate(&points).unwrap();
+        let delaunay = delaunator::triangulate(&all_points).unwrap();
 
         // Convert our Vec<Point> into Vec<Cell>
-        let mut cells: Vec<Cell> = points
-            .into_iter()
-            .map(|p| p.into())
-            .collect();
-        
+        let mut cells: Vec<Cell> = points.into_iter().map(|p| p.into()).collect();
+
         let rivers = Vec::new();
 
         // Build an index of points to an incoming half-edge; useful to find the point's cell
         // and neighbors later
         for e in 0..delaunay.triangles.len() {
             let edge = delaunay.triangles[delaunator::next_halfedge(e)];
-            if cells[edge].halfedge == usize::MAX {
+            if edge < cells.len() && cells[edge].halfedge == usize::MAX {
                 cells[edge].halfedge = e;
             }
         }
 
+        let corners = delaunay.len();
+
         Voronoi {
             width,
             height,
             cells,
+            ghosts,
             delaunay,
+            corner_heights: vec![0.0; corners],
+            downslope: vec![None; corners],
+            flow: vec![0.0; corners],
+            strahler: vec![Strahler::default(); corners],
             rivers,
         }
     }
 
+    /// Build a polygon map of the terrain of `map`
+    ///
+    /// Heights are sampled from the map onto our cells and corners; rivers then run along the
+    /// edges between cells, and biomes are assigned by height and by moisture from fresh water.
+    ///
+    /// # Arguments
+    ///
+    /// * `map` - The map to sample
+    /// * `seed` - The RNG seed for generating our Voronoi "seeds"
+    /// * `sampling` - How far apart to place our seeds, and how to relax them
+    pub fn from_map(map: &Map, seed: u64, sampling: &Sampling) -> Voronoi {
+        let size = map.size();
+        let mut voronoi = match sampling.density {
+            Density::Terrain => {
+                let density = sampling::terrain_density(map);
+                Self::build(seed, size, size, sampling, Some(&density))
+            }
+            _ => Self::with_sampling(seed, size, size, sampling),
+        };
+
+        voronoi.sample_terrain(map);
+        voronoi.route_water();
+        voronoi.find_rivers();
+        voronoi.assign_moisture();
+        voronoi.assign_biomes();
+
+        voronoi
+    }
+
+    /// Sample heights onto cells and corners, and find which cells are water
+    fn sample_terrain(&mut self, map: &Map) {
+        let max = f64::from(map.size() - 1);
+        let pixel = |v: f64| v.round().clamp(0.0, max) as u32;
+
+        for cell in self.cells.iter_mut() {
+            cell.height = map.sample_elevation(cell.x, cell.y);
+
+            let (x, y) = (pixel(cell.x), pixel(cell.y));
+            cell.biome = if map.is_ocean(x, y) {
+                Biome::Ocean
+            } else if cell.height <= SEA_LEVEL {
+                Biome::Lagoon
+            } else if map.get_water_depth(x, y) > LAKE_DEPTH {
+                Biome::Lake
+            } else {
+                // A placeholder until we've found our moisture
+                Biome::Grassland
+            };
+        }
+
+        // Circumcenters of triangles on the hull can lie well outside the map; clamp them to it
+        self.corner_heights = (0..self.delaunay.len())
+            .map(|t| {
+                let center = self.triangle_center(t);
+                map.sample_elevation(center.x.clamp(0.0, max), center.y.clamp(0.0, max))
+            })
+            .collect();
+    }
+
+    /// Route water from every land corner to the sea, and count the flow through each corner
+    ///
+    /// Like `Drainage`, this uses a priority flood, so water always finds its way out of pits.
+    fn route_water(&mut self) {
+        let corners = self.delaunay.len();
+        let mut visited = vec![false; corners];
+        let mut queue = BinaryHeap::new();
+        // Land corners, ordered so that each comes after the corner it drains into
+        let mut stack = Vec::new();
+
+        // Our flood starts from the sea, which includes the edge of the map
+        for (corner, seen) in visited.iter_mut().enumerate() {
+            if self.is_sea_corner(corner) {
+                *seen = true;
+                queue.push(Queued {
+                    height: self.corner_heights[corner],
+                    idx: corner,
+                });
+            }
+        }
+
+        while let Some(Queued {
+            height,
+            idx: corner,
+        }) = queue.pop()
+        {
+            for neighbor in self.adjacent_triangles(corner) {
+                if visited[neighbor] {
+                    continue;
+                }
+                visited[neighbor] = true;
+
+                self.downslope[neighbor] = Some(corner);
+                stack.push(neighbor);
+                queue.push(Queued {
+                    height: self.corner_heights[neighbor].max(height),
+                    idx: neighbor,
+                });
+            }
+        }
+
+        // Every land corner catches the same rain, which flows on downslope
+        for &corner in stack.iter() {
+            self.flow[corner] = 1.0;
+        }
+        for &corner in stack.iter().rev() {
+            if let Some(down) = self.downslope[corner] {
+                self.flow[down] += self.flow[corner];
+            }
+        }
+        // Find our rivers' Strahler orders, working downstream from their sources
+        let mut inflows: Vec<Vec<Strahler>> = vec![Vec::new(); corners];
+        for &corner in stack.iter().rev() {
+            if self.flow[corner] < RIVER_FLOW {
+                continue;
+            }
+
+            let order = Strahler::confluence(std::mem::take(&mut inflows[corner]));
+            self.strahler[corner] = order;
+
+            if let Some(down) = self.downslope[corner] {
+                inflows[down].push(order);
+            }
+        }
+    }
+
+    /// Whether the given corner touches the sea, or the edge of the map
+    fn is_sea_corner(&self, corner: usize) -> bool {
+        self.points_of_triangle(corner).into_iter().any(|p| {
+            self.is_ghost(p)
+                || matches!(
+                    self.cells[p].biome,
+                    Biome::Ocean | Biome::Coast | Biome::Lagoon
+                )
+        })
+    }
+
+    /// Split our river network into stretches of constant Strahler order
+    fn find_rivers(&mut self) {
+        let none = Strahler::default();
+
+        // Count the upstream corners that carry the same order of river into each corner
+        let mut continued = vec![false; self.delaunay.len()];
+        for (corner, down) in self.downslope.iter().enumerate() {
+            if let Some(down) = *down {
+                if self.strahler[corner] != none && self.strahler[corner] == self.strahler[down] {
+                    continued[down] = true;
+                }
+            }
+        }
+
+        self.rivers = (0..self.delaunay.len())
+            .filter(|&corner| self.strahler[corner] != none && !continued[corner])
+            .map(|start| {
+                let order = self.strahler[start];
+                let mut corners = vec![start];
+                let mut current = start;
+                while let Some(down) = self.downslope[current] {
+                    corners.push(down);
+                    if self.strahler[down] != order {
+                        break;
+                    }
+                    current = down;
+                }
+
+                River { corners, order }
+            })
+            .collect();
+    }
+
+    /// Assign moisture to land cells by their distance from rivers and lakes
+    fn assign_moisture(&mut self) {
+        let none = Strahler::default();
+        let mut distance = vec![usize::MAX; self.cells.len()];
+        let mut queue = VecDeque::new();
+
+        for (p, dist) in distance.iter_mut().enumerate() {
+            let fresh_water = self.cells[p].biome == Biome::Lake
+                || self
+                    .corners_of_point(p)
+                    .into_iter()
+                    .any(|corner| self.strahler[corner] != none);
+            if fresh_water {
+                *dist = 0;
+                queue.push_back(p);
+            }
+        }
+
+        while let Some(p) = queue.pop_front() {
+            for neighbor in self.neighbors_of_point(p) {
+                if distance[neighbor] == usize::MAX && !self.cells[neighbor].biome.is_water() {
+                    distance[neighbor] = distance[p] + 1;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        let moisture = rank_moisture(&distance, |p| !self.cells[p].biome.is_water());
+        for (cell, moisture) in self.cells.iter_mut().zip(moisture) {
+            cell.moisture = moisture;
+        }
+    }
+
+    /// Assign biomes to our cells, by height and moisture on land
+    fn assign_biomes(&mut self) {
+        for p in 0..self.cells.len() {
+            let neighbors = self.neighbors_of_point(p);
+            let biome = match self.cells[p].biome {
+                Biome::Ocean if neighbors.iter().any(|&n| !self.cells[n].biome.is_water()) => {
+                    Biome::Coast
+                }
+                biome if biome.is_water() => biome,
+                _ => {
+                    let cell = &self.cells[p];
+                    let coastal = neighbors
+                        .iter()
+                        .any(|&n| matches!(self.cells[n].biome, Biome::Ocean | Biome::Coast));
+                    if coastal && cell.height < BEACH_HEIGHT {
+                        Biome::Beach
+                    } else {
+                        Biome::classify(cell.height, cell.moisture)
+                    }
+                }
+            };
+            self.cells[p].biome = biome;
+        }
+    }
+
     /// For the specified triangle return its half-edges.
     ///
     /// We define a triangle to be represented by the half-edges that run counter-clockwise around its perimeter.
@@ -190,15 +569,27 @@ impl Voronoi {
     /// Find the circumcenter of the given triangle
     pub fn triangle_center(&self, triangle: usize) -> Point {
         let p = self.points_of_triangle(triangle);
-        self.circumcenter(&self.cells[p[0]].as_point(), &self.cells[p[1]].as_point(), &self.cells[p[2]].as_point())
+        self.circumcenter(&self.point(p[0]), &self.point(p[1]), &self.point(p[2]))
+    }
+
+    /// Whether the given point is one of the ghost points surrounding our map
+    pub fn is_ghost(&self, point: usize) -> bool {
+        point >= self.cells.len()
+    }
+
+    /// Get the location of any point in our triangulation, including ghost points
+    pub fn point(&self, point: usize) -> Point {
+        if self.is_ghost(point) {
+            self.ghosts[point - self.cells.len()].clone()
+        } else {
+            self.cells[point].as_point()
+        }
     }
 
     /// Find the edges that point in to the specified start point.
     ///
-    /// # Bugs
-    ///
-    /// Certain polygons on the convex hull will return incomplete sets of halfedges due to some
-    /// triangulations being empty.
+    /// Every cell is surrounded by ghost points, so these always go all the way around the cell;
+    /// only the ghost points themselves lie on the convex hull, where the set is incomplete.
     pub fn edges_around_point(&self, start: usize) -> Vec<usize> {
         let mut result = Vec::new();
         let mut incoming = start;
@@ -207,7 +598,6 @@ impl Voronoi {
             let outgoing = self.next_halfedge(incoming);
             incoming = self.delaunay.halfedges[outgoing];
 
-            // TODO: This breaks on certain polygons on the convex hull
             if incoming == delaunator::EMPTY || incoming == start {
                 break;
             }
@@ -218,9 +608,306 @@ impl Voronoi {
 
     /// Find the points in polygons that neighbor the given point
     pub fn neighbors_of_point(&self, point: usize) -> Vec<usize> {
+        if self.cells[point].halfedge == usize::MAX {
+            return Vec::new();
+        }
+
         self.edges_around_point(self.cells[point].halfedge)
             .into_iter()
             .map(|edge| self.delaunay.triangles[edge])
+            .filter(|&neighbor| !self.is_ghost(neighbor))
+            .collect()
+    }
+
+    /// Find the corners (i.e. triangles) around the given point's polygon
+    pub fn corners_of_point(&self, point: usize) -> Vec<usize> {
+        if self.cells[point].halfedge == usize::MAX {
+            return Vec::new();
+        }
+
+        self.edges_around_point(self.cells[point].halfedge)
+            .into_iter()
+            .map(|edge| self.triangle_of_edge(edge))
             .collect()
     }
+
+    /// Find the vertices of the given point's polygon, clipped to the bounds of the map
+    ///
+    /// Vertices are returned in counter-clockwise order.
+    pub fn cell_polygon(&self, point: usize) -> Vec<Point> {
+        let polygon = self
+            .corners_of_point(point)
+            .into_iter()
+            .map(|corner| self.triangle_center(corner))
+            .collect();
+
+        clip(polygon, f64::from(self.width), f64::from(self.height))
+    }
+}
+
+/// Find the centroid of a polygon
+fn centroid(polygon: &[Point]) -> Point {
+    let (mut x, mut y, mut area) = (0.0, 0.0, 0.0);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+        let cross = a.x * b.y - b.x * a.y;
+        x += (a.x + b.x) * cross;
+        y += (a.y + b.y) * cross;
+        area += cross;
+    }
+
+    Point {
+        x: x / (3.0 * area),
+        y: y / (3.0 * area),
+    }
+}
+
+/// Generate a ring of points, `spacing` apart, around a map of the given size
+///
+/// As long as no point on the map is more than `reach` from one of our cells, keeping our ghosts
+/// further out than that means none of their cells reach into the map.
+fn ghost_points(width: u32, height: u32, spacing: f64, reach: f64) -> Vec<Point> {
+    let margin = reach + spacing / 2.0;
+    let (left, top) = (-margin, -margin);
+    let (right, bottom) = (f64::from(width) + margin, f64::from(height) + margin);
+
+    let steps = |length: f64| (length / spacing).ceil().max(1.0) as u32;
+    let (across, down) = (steps(right - left), steps(bottom - top));
+
+    let mut ghosts = Vec::new();
+    for i in 0..=across {
+        let x = left + (right - left) * f64::from(i) / f64::from(across);
+        ghosts.push(Point { x, y: top });
+        ghosts.push(Point { x, y: bottom });
+    }
+    for i in 1..down {
+        let y = top + (bottom - top) * f64::from(i) / f64::from(down);
+        ghosts.push(Point { x: left, y });
+        ghosts.push(Point { x: right, y });
+    }
+
+    ghosts
+}
+
+/// Clip a polygon to the rectangle from (0, 0) to (width, height)
+///
+/// This is the Sutherland-Hodgman algorithm, clipping against each edge of the rectangle in turn.
+fn clip(polygon: Vec<Point>, width: f64, height: f64) -> Vec<Point> {
+    // Each edge of our rectangle, as the axis it cuts (true for x) and where, and which side of
+    // it is inside
+    let edges = [
+        (true, 0.0, 1.0),
+        (true, width, -1.0),
+        (false, 0.0, 1.0),
+        (false, height, -1.0),
+    ];
+
+    edges.iter().fold(polygon, |polygon, &(is_x, at, inward)| {
+        let coord = |p: &Point| if is_x { p.x } else { p.y };
+        let inside = |p: &Point| (coord(p) - at) * inward >= 0.0;
+        let crossing = |a: &Point, b: &Point| {
+            let t = (at - coord(a)) / (coord(b) - coord(a));
+            if is_x {
+                Point {
+                    x: at,
+                    y: a.y + (b.y - a.y) * t,
+                }
+            } else {
+                Point {
+                    x: a.x + (b.x - a.x) * t,
+                    y: at,
+                }
+            }
+        };
+
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (i, current) in polygon.iter().enumerate() {
+            let previous = &polygon[(i + polygon.len() - 1) % polygon.len()];
+            match (inside(previous), inside(current)) {
+                (true, true) => clipped.push(current.clone()),
+                (true, false) => clipped.push(crossing(previous, current)),
+                (false, true) => {
+                    clipped.push(crossing(previous, current));
+                    clipped.push(current.clone());
+                }
+                (false, false) => {}
+            }
+        }
+
+        clipped
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbors_are_mutual() {
+        let voronoi = Voronoi::new(3, 100, 80);
+
+        for point in 0..voronoi.cells.len() {
+            let neighbors = voronoi.neighbors_of_point(point);
+            assert!(
+                neighbors.len() >= 2,
+                "Point {} has too few neighbors",
+                point
+            );
+
+            for neighbor in neighbors {
+                assert!(
+                    voronoi.neighbors_of_point(neighbor).contains(&point),
+                    "{} neighbors {} but not the other way around",
+                    point,
+                    neighbor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn polygons_tile_the_map() {
+        let (width, height) = (100, 80);
+        for seed in 0..5 {
+            let voronoi = Voronoi::new(seed, width, height);
+
+            let mut total = 0.0;
+            for point in 0..voronoi.cells.len() {
+                let polygon = voronoi.cell_polygon(point);
+                assert!(polygon.len() >= 3);
+
+                let mut area = 0.0;
+                for (i, a) in polygon.iter().enumerate() {
+                    assert!(a.x >= 0.0 && a.x <= f64::from(width));
+                    assert!(a.y >= 0.0 && a.y <= f64::from(height));
+
+                    let b = &polygon[(i + 1) % polygon.len()];
+                    area += a.x * b.y - b.x * a.y;
+                }
+                // Counter-clockwise polygons have positive area
+                assert!(area > 0.0, "Polygon {} is clockwise or empty", point);
+                total += area / 2.0;
+            }
+
+            // Together our cells should cover the whole map, with no gaps or overlaps
+            assert!((total - f64::from(width * height)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn relaxation_keeps_tiling() {
+        let (width, height) = (100, 80);
+        let sampling = Sampling {
+            relaxation: 2,
+            ..Default::default()
+        };
+        let voronoi = Voronoi::with_sampling(1, width, height, &sampling);
+
+        let total: f64 = (0..voronoi.cells.len())
+            .map(|point| {
+                let polygon = voronoi.cell_polygon(point);
+                (0..polygon.len())
+                    .map(|i| {
+                        let (a, b) = (&polygon[i], &polygon[(i + 1) % polygon.len()]);
+                        a.x * b.y - b.x * a.y
+                    })
+                    .sum::<f64>()
+                    / 2.0
+            })
+            .sum();
+
+        assert!((total - f64::from(width * height)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn density_varies_cell_size() {
+        // Dense on the left, sparse on the right
+        let density = |x: f64, _: f64| if x < 50.0 { 1.0 } else { 0.0 };
+        let sampling = Sampling {
+            density: Density::Custom(&density),
+            ..Default::default()
+        };
+        let voronoi = Voronoi::with_sampling(1, 100, 100, &sampling);
+
+        let left = voronoi.cells.iter().filter(|cell| cell.x < 50.0).count();
+        let right = voronoi.cells.len() - left;
+        assert!(
+            left > right * 3,
+            "{} cells on the left, {} on the right",
+            left,
+            right
+        );
+    }
+
+    #[test]
+    fn thinned_edges_are_clipped_not_open() {
+        // Sparse everywhere, so our seeds are thinned out as far as they go
+        let (width, height) = (100, 80);
+        let density = |_: f64, _: f64| 0.0;
+        let sampling = Sampling {
+            density: Density::Custom(&density),
+            ..Default::default()
+        };
+
+        for seed in 0..5 {
+            let voronoi = Voronoi::with_sampling(seed, width, height, &sampling);
+
+            // Only ghosts lie on the hull, so every real cell is closed...
+            assert!(voronoi
+                .delaunay
+                .hull
+                .iter()
+                .all(|&p| p >= voronoi.cells.len()));
+
+            // ...and the cells along the edges are cut off by it, with no ghost cell reaching in
+            let mut total = 0.0;
+            let mut clipped = 0;
+            for point in 0..voronoi.cells.len() {
+                let polygon = voronoi.cell_polygon(point);
+                assert!(polygon.len() >= 3, "Cell {} is open", point);
+
+                let on_edge = |p: &Point| {
+                    p.x == 0.0 || p.y == 0.0 || p.x == f64::from(width) || p.y == f64::from(height)
+                };
+                if polygon.iter().any(on_edge) {
+                    clipped += 1;
+                }
+                total += (0..polygon.len())
+                    .map(|i| {
+                        let (a, b) = (&polygon[i], &polygon[(i + 1) % polygon.len()]);
+                        a.x * b.y - b.x * a.y
+                    })
+                    .sum::<f64>()
+                    / 2.0;
+            }
+
+            assert!(clipped > 0);
+            assert!((total - f64::from(width * height)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn rivers_reach_the_sea() {
+        let mut map = Map::new(2, 200);
+        let voronoi = map.polygons();
+
+        assert!(!voronoi.rivers.is_empty());
+        for river in voronoi.rivers.iter() {
+            // Every stretch of river follows the drainage...
+            for (a, b) in river.segments() {
+                assert_eq!(voronoi.downslope[a], Some(b));
+            }
+            // ...and ends in the sea or in a river at least as large as itself
+            let mouth = river.mouth();
+            assert!(voronoi.downslope[mouth].is_none() || voronoi.strahler[mouth] >= river.order);
+        }
+
+        for cell in voronoi.cells.iter() {
+            if cell.biome.is_water() {
+                continue;
+            }
+            assert!(cell.height > SEA_LEVEL);
+            assert!((0.0..=1.0).contains(&cell.moisture));
+        }
+    }
 }
@@ -0,0 +1,30 @@
+use crate::map::Strahler;
+
+/// A stretch of river running along the edges of our Voronoi cells
+///
+/// Each river runs from its source, or from the confluence where it reached its current order,
+/// down to the point where it either joins a larger river or reaches the water.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct River {
+    /// The corners (i.e. Delaunay triangles) this river passes through, in source-to-mouth order
+    pub corners: Vec<usize>,
+    /// Strahler order of this stretch of river
+    pub order: Strahler,
+}
+
+#[allow(dead_code)]
+impl River {
+    /// The river's segments as pairs of corners
+    pub fn segments(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.corners.windows(2).map(|pair| (pair[0], pair[1]))
+    }
+
+    pub fn source(&self) -> usize {
+        self.corners[0]
+    }
+
+    pub fn mouth(&self) -> usize {
+        self.corners[self.corners.len() - 1]
+    }
+}
@@ -0,0 +1,140 @@
+//! How we place the seeds of our Voronoi cells
+//!
+//! Seeds come from Poisson disk sampling, which keeps them evenly spaced without the regularity
+//! of a grid. A density map can vary that spacing across the map, giving smaller cells where
+//! there's more detail to show, and Lloyd relaxation can then even out the cells' shapes.
+
+use crate::map::{Map, SEA_LEVEL};
+use delaunator::Point;
+use fast_poisson::Poisson2D;
+
+/// Distance from the coast, in cells, within which the terrain density map favors the coast
+const COAST_BAND: f64 = 20.0;
+
+/// Where to put our cells' seeds, and how far apart
+#[derive(Clone, Copy)]
+pub struct Sampling<'a> {
+    /// Minimum distance between seeds, where the density is highest
+    pub radius: f64,
+    /// Minimum distance between seeds where the density is lowest
+    ///
+    /// This has no effect with uniform density.
+    pub max_radius: f64,
+    /// Rounds of Lloyd relaxation to apply, moving each seed to the centroid of its cell
+    ///
+    /// Relaxation evens out the shapes of our cells, but also evens out their sizes, so the more
+    /// rounds we apply the less a density map will show.
+    pub relaxation: u32,
+    /// How densely to place our seeds across the map
+    pub density: Density<'a>,
+}
+
+impl Default for Sampling<'_> {
+    fn default() -> Self {
+        Self {
+            radius: 5.0,
+            max_radius: 12.0,
+            relaxation: 0,
+            density: Density::Uniform,
+        }
+    }
+}
+
+/// How the density of our seeds varies across the map
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum Density<'a> {
+    /// The same density everywhere
+    Uniform,
+    /// Densest along the coast and on steep slopes
+    ///
+    /// This needs terrain to work from, so is only used by `Voronoi::from_map`; elsewhere it's the
+    /// same as `Uniform`.
+    Terrain,
+    /// Density given by a function of (x, y), from 0.0 for our sparsest seeds to 1.0 for our
+    /// densest
+    Custom(&'a dyn Fn(f64, f64) -> f64),
+}
+
+/// A density map that's highest along the coast and on steep slopes of the given map
+pub(super) fn terrain_density(map: &Map) -> impl Fn(f64, f64) -> f64 + '_ {
+    let max = f64::from(map.size() - 1);
+    let height = move |x: f64, y: f64| map.sample_elevation(x.clamp(0.0, max), y.clamp(0.0, max));
+
+    move |x, y| {
+        let h = height(x, y) - SEA_LEVEL;
+        let dx = height(x + 1.0, y) - height(x - 1.0, y);
+        let dy = height(x, y + 1.0) - height(x, y - 1.0);
+        let gradient = (dx * dx + dy * dy).sqrt() / 2.0;
+
+        // Estimate our distance to the coast by how far we'd have to follow the slope to reach it
+        let coast = if gradient > 0.0 {
+            1.0 - (h.abs() / gradient / COAST_BAND).min(1.0)
+        } else {
+            0.0
+        };
+        // Only the land's slopes interest us; the sea floor is hidden anyway
+        let steep = if h > 0.0 {
+            (gradient * crate::map::HEIGHT_SCALE).min(1.0)
+        } else {
+            0.0
+        };
+
+        coast.max(steep)
+    }
+}
+
+/// Generate our seeds within the given bounds
+///
+/// With a density map, we start from seeds spaced at our smallest radius and then thin them out,
+/// keeping a seed only if no seed we've already kept lies within the radius the density map gives
+/// at that point. Seeds from `Poisson2D` grow outward from their neighbors, so this keeps them
+/// well spread.
+pub(super) fn points(
+    seed: u64,
+    width: u32,
+    height: u32,
+    sampling: &Sampling,
+    density: Option<&dyn Fn(f64, f64) -> f64>,
+) -> Vec<Point> {
+    let candidates = Poisson2D::new()
+        .with_dimensions([f64::from(width), f64::from(height)], sampling.radius)
+        .with_seed(seed)
+        .iter()
+        .map(|[x, y]| Point { x, y });
+
+    let density = match density {
+        Some(density) if sampling.max_radius > sampling.radius => density,
+        _ => return candidates.collect(),
+    };
+
+    // Bucket our kept seeds into a grid, to quickly find those near each candidate
+    let cell = sampling.radius;
+    let columns = (f64::from(width) / cell).ceil() as usize + 1;
+    let rows = (f64::from(height) / cell).ceil() as usize + 1;
+    let reach = (sampling.max_radius / cell).ceil() as usize;
+    let mut grid: Vec<Vec<usize>> = vec![Vec::new(); columns * rows];
+
+    let mut points: Vec<Point> = Vec::new();
+    for candidate in candidates {
+        let t = density(candidate.x, candidate.y).clamp(0.0, 1.0);
+        let radius = sampling.max_radius + (sampling.radius - sampling.max_radius) * t;
+
+        let (column, row) = ((candidate.x / cell) as usize, (candidate.y / cell) as usize);
+        let crowded = (row.saturating_sub(reach)..(row + reach + 1).min(rows)).any(|r| {
+            (column.saturating_sub(reach)..(column + reach + 1).min(columns)).any(|c| {
+                grid[r * columns + c].iter().any(|&p| {
+                    let (dx, dy) = (points[p].x - candidate.x, points[p].y - candidate.y);
+                    dx * dx + dy * dy < radius * radius
+                })
+            })
+        });
+
+        if !crowded {
+            grid[row * columns + column].push(points.len());
+            points.push(candidate);
+        }
+    }
+
+    points
+}
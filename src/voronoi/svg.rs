@@ -0,0 +1,230 @@
+//! Render polygon maps to SVG
+//!
+//! Each cell is drawn as a polygon filled by its biome, with rivers drawn along the cell edges,
+//! wider for higher Strahler orders, and the coastline traced around the land. Each of these is
+//! its own layer, and can be turned on or off with `Layers`.
+
+use super::{Biome, Region, Voronoi};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Width of a first-order river, in map units
+const RIVER_WIDTH: f64 = 0.8;
+
+/// Which layers to draw
+#[derive(Debug, Clone, Copy)]
+pub struct Layers {
+    /// Fill each cell by its biome
+    pub cells: bool,
+    /// Draw rivers along the cell edges
+    pub rivers: bool,
+    /// Outline the coast
+    pub coast: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self {
+            cells: true,
+            rivers: true,
+            coast: true,
+        }
+    }
+}
+
+impl Biome {
+    /// Colour to draw this biome in
+    ///
+    /// Colours follow Amit Patel's polygon map generator.
+    pub fn color(&self) -> [u8; 3] {
+        match self {
+            Self::Ocean => [0x44, 0x44, 0x7a],
+            Self::Coast => [0x33, 0x33, 0x5a],
+            Self::Lagoon => [0x44, 0x66, 0x99],
+            Self::Lake => [0x33, 0x66, 0x99],
+            Self::Beach => [0xa0, 0x90, 0x77],
+            Self::Snow => [0xff, 0xff, 0xff],
+            Self::Tundra => [0xbb, 0xbb, 0xaa],
+            Self::Bare => [0x88, 0x88, 0x88],
+            Self::Scorched => [0x55, 0x55, 0x55],
+            Self::Taiga => [0x99, 0xaa, 0x77],
+            Self::Shrubland => [0x88, 0x99, 0x77],
+            Self::TemperateDesert => [0xc9, 0xd2, 0x9b],
+            Self::TemperateRainForest => [0x44, 0x88, 0x55],
+            Self::TemperateDeciduousForest => [0x67, 0x94, 0x59],
+            Self::Grassland => [0x88, 0xaa, 0x55],
+            Self::TropicalRainForest => [0x33, 0x77, 0x55],
+            Self::TropicalSeasonalForest => [0x55, 0x99, 0x44],
+            Self::SubtropicalDesert => [0xd2, 0xb9, 0x8b],
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Voronoi {
+    /// Render this map as an SVG document, drawing only the given layers
+    pub fn to_svg(&self, layers: &Layers) -> String {
+        let mut svg = String::new();
+        // Writing to a String can't fail, so we can safely ignore the results of write!
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {w} {h}" width="{w}" height="{h}">"#,
+            w = self.width,
+            h = self.height,
+        );
+
+        if layers.cells {
+            svg.push_str("<g id=\"cells\">\n");
+            for (point, cell) in self.cells.iter().enumerate() {
+                let [r, g, b] = cell.biome.color();
+                let fill = format!("#{:02x}{:02x}{:02x}", r, g, b);
+                let points: Vec<_> = self
+                    .cell_polygon(point)
+                    .iter()
+                    .map(|p| format!("{:.2},{:.2}", p.x, p.y))
+                    .collect();
+                // Stroke each cell in its own colour to hide hairline gaps between them
+                let _ = writeln!(
+                    svg,
+                    r#"<polygon points="{}" fill="{}" stroke="{}" stroke-width="0.5"/>"#,
+                    points.join(" "),
+                    fill,
+                    fill
+                );
+            }
+            svg.push_str("</g>\n");
+        }
+
+        if layers.rivers {
+            svg.push_str(
+                "<g id=\"rivers\" fill=\"none\" stroke=\"#225588\" stroke-linecap=\"round\" \
+                 stroke-linejoin=\"round\">\n",
+            );
+            for river in self.rivers.iter() {
+                let width = RIVER_WIDTH * f64::from(u32::from(river.order));
+                let _ = writeln!(
+                    svg,
+                    r#"<path d="{}" stroke-width="{:.2}"/>"#,
+                    self.path(&river.corners, false),
+                    width
+                );
+            }
+            svg.push_str("</g>\n");
+        }
+
+        if layers.coast {
+            svg.push_str("<g id=\"coast\" fill=\"none\" stroke=\"#222222\" stroke-width=\"1\">\n");
+            for coast in self.coastlines() {
+                let closed = coast.len() > 2 && coast.first() == coast.last();
+                let _ = writeln!(svg, r#"<path d="{}"/>"#, self.path(&coast, closed));
+            }
+            svg.push_str("</g>\n");
+        }
+
+        svg.push_str("</svg>\n");
+
+        svg
+    }
+
+    /// Render this map and save it as an SVG file
+    pub fn save_svg<P: AsRef<Path>>(&self, path: P, layers: &Layers) -> std::io::Result<()> {
+        std::fs::write(path, self.to_svg(layers))
+    }
+
+    /// Trace the coastline as paths of corners, following the edges between land and sea cells
+    fn coastlines(&self) -> Vec<Vec<usize>> {
+        let is_sea = |region: Option<Region>| {
+            region.map_or(true, |region| {
+                matches!(
+                    region.cell().biome,
+                    Biome::Ocean | Biome::Coast | Biome::Lagoon
+                )
+            })
+        };
+
+        // Find every edge between land and sea, and index them by the corners they touch
+        let mut segments = Vec::new();
+        let mut at_corner: HashMap<usize, Vec<usize>> = HashMap::new();
+        for edge in self.edges() {
+            let (a, b) = edge.regions();
+            if is_sea(Some(a)) == is_sea(b) {
+                continue;
+            }
+
+            let (c1, c2) = edge.corners();
+            let segment = (c1.id(), c2.id());
+            at_corner.entry(segment.0).or_default().push(segments.len());
+            at_corner.entry(segment.1).or_default().push(segments.len());
+            segments.push(segment);
+        }
+
+        // Merge our segments into paths by walking from corner to corner
+        let mut used = vec![false; segments.len()];
+        let mut coastlines = Vec::new();
+        for start in 0..segments.len() {
+            if used[start] {
+                continue;
+            }
+            used[start] = true;
+
+            let (first, mut current) = segments[start];
+            let mut coast = vec![first, current];
+            while let Some(&next) = at_corner[&current].iter().find(|&&s| !used[s]) {
+                used[next] = true;
+                let (a, b) = segments[next];
+                current = if a == current { b } else { a };
+                coast.push(current);
+            }
+
+            coastlines.push(coast);
+        }
+
+        coastlines
+    }
+
+    /// Build SVG path data running through the given corners
+    fn path(&self, corners: &[usize], closed: bool) -> String {
+        let mut d = String::new();
+        for (i, &corner) in corners.iter().enumerate() {
+            let p = self.triangle_center(corner);
+            let _ = write!(
+                d,
+                "{}{:.2},{:.2} ",
+                if i == 0 { "M" } else { "L" },
+                p.x,
+                p.y
+            );
+        }
+        if closed {
+            d.push('Z');
+        }
+
+        d.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Map;
+
+    #[test]
+    fn layers_can_be_toggled() {
+        let mut map = Map::new(2, 120);
+        let voronoi = map.polygons();
+
+        let svg = voronoi.to_svg(&Layers::default());
+        assert_eq!(svg.matches("<polygon").count(), voronoi.cells.len());
+        assert!(svg.matches("<path").count() > voronoi.rivers.len());
+
+        let svg = voronoi.to_svg(&Layers {
+            cells: false,
+            rivers: true,
+            coast: false,
+        });
+        assert!(!svg.contains("id=\"cells\""));
+        assert!(!svg.contains("id=\"coast\""));
+        assert_eq!(svg.matches("<path").count(), voronoi.rivers.len());
+    }
+}